@@ -2,3 +2,5 @@
 set -ex
 cargo +nightly fuzz run fuzz_valid -- -max_total_time=15
 cargo +nightly fuzz run fuzz_invalid -- -max_total_time=15
+cargo +nightly fuzz run fuzz_nested -- -max_total_time=15
+cargo +nightly fuzz run fuzz_cwt -- -max_total_time=15
@@ -0,0 +1,310 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use base64::Engine;
+
+/// Data items nested deeper than this are rejected, to not overflow the stack on hostile input
+const MAX_DEPTH: usize = 64;
+
+/// [RFC-8949](https://datatracker.ietf.org/doc/html/rfc8949#section-3)
+/// A decoded data item
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Cbor {
+    Uint(u64),
+    /// The value is `-1 - n`
+    Nint(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    /// Kept in the encoded order, keys can be of any type
+    Map(Vec<(Cbor, Cbor)>),
+    Tag(u64, Box<Cbor>),
+    Bool(bool),
+    Null,
+    Undefined,
+    Simple(u8),
+    Float(f64),
+}
+
+impl Cbor {
+    /// Decodes a single data item, which has to span all of the input
+    pub(crate) fn decode(raw: &[u8]) -> Result<Self, String> {
+        let mut decoder = Decoder { raw, pos: 0 };
+        let item = decoder.item(0)?;
+        if decoder.pos != raw.len() {
+            return Err(format!(
+                "CBOR data item ended at byte {}, but the input is {} bytes",
+                decoder.pos,
+                raw.len()
+            ));
+        }
+        Ok(item)
+    }
+
+    /// The value of an integer, as an `i64` if it fits
+    pub(crate) fn as_i64(&self) -> Option<i64> {
+        match self {
+            Cbor::Uint(n) => i64::try_from(*n).ok(),
+            Cbor::Nint(n) => i64::try_from(*n).ok().map(|n| -1 - n),
+            _ => None,
+        }
+    }
+
+    /// [RFC-8949](https://datatracker.ietf.org/doc/html/rfc8949#section-6.1)
+    /// Byte strings are shown as base64url, and map keys that aren't text are stringified
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        match self {
+            Cbor::Uint(n) => (*n).into(),
+            Cbor::Nint(n) => match i64::try_from(*n) {
+                Ok(n) => (-1 - n).into(),
+                // Below `i64::MIN`, which JSON numbers here can't represent
+                Err(_) => serde_json::Value::String(format!("-{}", u128::from(*n) + 1)),
+            },
+            Cbor::Bytes(bytes) => serde_json::Value::String(
+                base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes),
+            ),
+            Cbor::Text(text) => serde_json::Value::String(text.clone()),
+            Cbor::Array(items) => items.iter().map(Cbor::to_json).collect(),
+            Cbor::Map(entries) => serde_json::Value::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.to_json_key(), value.to_json()))
+                    .collect(),
+            ),
+            // Tags like epoch-based date/time (1) are transparent
+            Cbor::Tag(_, item) => item.to_json(),
+            Cbor::Bool(b) => serde_json::Value::Bool(*b),
+            Cbor::Null | Cbor::Undefined => serde_json::Value::Null,
+            Cbor::Simple(n) => serde_json::Value::String(format!("simple({n})")),
+            Cbor::Float(f) => serde_json::Number::from_f64(*f)
+                .map_or_else(|| serde_json::Value::String(format!("{f}")), Into::into),
+        }
+    }
+
+    pub(crate) fn to_json_key(&self) -> String {
+        match self {
+            Cbor::Text(text) => text.clone(),
+            other => match other.to_json() {
+                serde_json::Value::String(s) => s,
+                json => json.to_string(),
+            },
+        }
+    }
+}
+
+struct Decoder<'a> {
+    raw: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.raw.len())
+            .ok_or_else(|| {
+                format!(
+                    "CBOR input ended early, needed {len} bytes at byte {}",
+                    self.pos
+                )
+            })?;
+        let taken = &self.raw[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// [RFC-8949](https://datatracker.ietf.org/doc/html/rfc8949#section-3)
+    /// The argument following the initial byte, `None` for an indefinite length
+    fn argument(&mut self, info: u8) -> Result<Option<u64>, String> {
+        let value = match info {
+            0..=23 => u64::from(info),
+            24 => u64::from(self.byte()?),
+            25 => u64::from(u16::from_be_bytes([self.byte()?, self.byte()?])),
+            26 => {
+                let bytes = self.take(4)?;
+                u64::from(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+            27 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                u64::from_be_bytes(bytes)
+            }
+            31 => return Ok(None),
+            _ => {
+                return Err(format!(
+                    "Reserved CBOR additional information {info} at byte {}",
+                    self.pos - 1
+                ))
+            }
+        };
+        Ok(Some(value))
+    }
+
+    fn definite(&mut self, info: u8) -> Result<u64, String> {
+        self.argument(info)?.ok_or_else(|| {
+            format!(
+                "Indefinite length where it isn't allowed at byte {}",
+                self.pos - 1
+            )
+        })
+    }
+
+    /// A length has to fit in what's left of the input, it can't be larger than that
+    fn length(&mut self, len: u64) -> Result<usize, String> {
+        usize::try_from(len)
+            .ok()
+            .filter(|len| *len <= self.raw.len() - self.pos)
+            .ok_or_else(|| {
+                format!(
+                    "CBOR length {len} at byte {} is longer than the input",
+                    self.pos
+                )
+            })
+    }
+
+    /// The initial byte of an indefinite length item's next chunk, or `None` at the break
+    fn next_chunk(&mut self) -> Result<Option<u8>, String> {
+        let initial = self.byte()?;
+        if initial == 0xff {
+            Ok(None)
+        } else {
+            Ok(Some(initial))
+        }
+    }
+
+    fn item(&mut self, depth: usize) -> Result<Cbor, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!(
+                "CBOR is nested deeper than the max depth of {MAX_DEPTH}"
+            ));
+        }
+        let initial = self.byte()?;
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        match major {
+            0 => Ok(Cbor::Uint(self.definite(info)?)),
+            1 => Ok(Cbor::Nint(self.definite(info)?)),
+            2 => Ok(Cbor::Bytes(self.string(major, info)?)),
+            3 => {
+                let raw = self.string(major, info)?;
+                String::from_utf8(raw)
+                    .map(Cbor::Text)
+                    .map_err(|_e| "CBOR text string was not valid utf8".to_string())
+            }
+            4 => {
+                let mut items = Vec::new();
+                if let Some(len) = self.argument(info)? {
+                    for _ in 0..self.length(len)? {
+                        items.push(self.item(depth + 1)?);
+                    }
+                } else {
+                    while self.raw.get(self.pos) != Some(&0xff) {
+                        items.push(self.item(depth + 1)?);
+                    }
+                    self.pos += 1;
+                }
+                Ok(Cbor::Array(items))
+            }
+            5 => {
+                let mut entries = Vec::new();
+                if let Some(len) = self.argument(info)? {
+                    for _ in 0..self.length(len)? {
+                        entries.push((self.item(depth + 1)?, self.item(depth + 1)?));
+                    }
+                } else {
+                    while self.raw.get(self.pos) != Some(&0xff) {
+                        entries.push((self.item(depth + 1)?, self.item(depth + 1)?));
+                    }
+                    self.pos += 1;
+                }
+                Ok(Cbor::Map(entries))
+            }
+            6 => {
+                let tag = self.definite(info)?;
+                Ok(Cbor::Tag(tag, Box::new(self.item(depth + 1)?)))
+            }
+            _ => self.simple(info),
+        }
+    }
+
+    /// A byte or text string, indefinite length strings are the concatenation of their
+    /// definite length chunks of the same major type
+    fn string(&mut self, major: u8, info: u8) -> Result<Vec<u8>, String> {
+        if let Some(len) = self.argument(info)? {
+            let len = self.length(len)?;
+            return Ok(self.take(len)?.to_vec());
+        }
+        let mut out = Vec::new();
+        while let Some(initial) = self.next_chunk()? {
+            if initial >> 5 != major {
+                return Err(format!(
+                    "Indefinite length CBOR string had a chunk of major type {} at byte {}",
+                    initial >> 5,
+                    self.pos - 1
+                ));
+            }
+            let len = self.definite(initial & 0x1f)?;
+            let len = self.length(len)?;
+            out.extend_from_slice(self.take(len)?);
+        }
+        Ok(out)
+    }
+
+    /// [RFC-8949](https://datatracker.ietf.org/doc/html/rfc8949#section-3.3)
+    fn simple(&mut self, info: u8) -> Result<Cbor, String> {
+        match info {
+            20 => Ok(Cbor::Bool(false)),
+            21 => Ok(Cbor::Bool(true)),
+            22 => Ok(Cbor::Null),
+            23 => Ok(Cbor::Undefined),
+            0..=19 => Ok(Cbor::Simple(info)),
+            24 => Ok(Cbor::Simple(self.byte()?)),
+            25 => Ok(Cbor::Float(f16_to_f64(u16::from_be_bytes([
+                self.byte()?,
+                self.byte()?,
+            ])))),
+            26 => {
+                let bytes = self.take(4)?;
+                Ok(Cbor::Float(f64::from(f32::from_be_bytes([
+                    bytes[0], bytes[1], bytes[2], bytes[3],
+                ]))))
+            }
+            27 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                Ok(Cbor::Float(f64::from_be_bytes(bytes)))
+            }
+            _ => Err(format!(
+                "Unexpected CBOR simple value or break at byte {}",
+                self.pos - 1
+            )),
+        }
+    }
+}
+
+/// [RFC-8949](https://datatracker.ietf.org/doc/html/rfc8949#appendix-D)
+fn f16_to_f64(half: u16) -> f64 {
+    let exponent = i32::from((half >> 10) & 0x1f);
+    let mantissa = f64::from(half & 0x3ff);
+    let value = match exponent {
+        0 => mantissa * pow2(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * pow2(exponent - 25),
+    };
+    if half & 0x8000 == 0 {
+        value
+    } else {
+        -value
+    }
+}
+
+/// `f64::powi` requires std
+fn pow2(exponent: i32) -> f64 {
+    f64::from_bits(((1023 + i64::from(exponent)) as u64) << 52)
+}
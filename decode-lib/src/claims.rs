@@ -0,0 +1,214 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// NumericDate claims from the [IANA JWT claims registry](https://www.iana.org/assignments/jwt/jwt.xhtml)
+pub(crate) const DEFAULT_TIME_CLAIMS: &[&str] =
+    &["exp", "nbf", "iat", "auth_time", "updated_at", "toe"];
+
+/// A claim name, or a [RFC-6901](https://datatracker.ietf.org/doc/html/rfc6901) JSON pointer
+/// split into its unescaped reference tokens
+pub(crate) fn pointer_tokens(key: &str) -> Vec<String> {
+    let Some(pointer) = key.strip_prefix('/') else {
+        return alloc::vec![key.to_string()];
+    };
+    pointer
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect()
+}
+
+/// Calls `f` on every value the pointer resolves to, where a `*` token matches every array
+/// element or object member
+pub(crate) fn for_each_pointer_match(
+    value: &mut serde_json::Value,
+    tokens: &[String],
+    f: &mut dyn FnMut(&mut serde_json::Value),
+) {
+    let Some((token, rest)) = tokens.split_first() else {
+        f(value);
+        return;
+    };
+    match value {
+        serde_json::Value::Object(members) if token == "*" => {
+            for member in members.values_mut() {
+                for_each_pointer_match(member, rest, f);
+            }
+        }
+        serde_json::Value::Object(members) => {
+            if let Some(member) = members.get_mut(token.as_str()) {
+                for_each_pointer_match(member, rest, f);
+            }
+        }
+        serde_json::Value::Array(elements) if token == "*" => {
+            for element in elements {
+                for_each_pointer_match(element, rest, f);
+            }
+        }
+        serde_json::Value::Array(elements) => {
+            if let Some(element) = token
+                .parse::<usize>()
+                .ok()
+                .and_then(|ind| elements.get_mut(ind))
+            {
+                for_each_pointer_match(element, rest, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// [RFC-7519](https://datatracker.ietf.org/doc/html/rfc7519#section-4.1.4)
+/// Checks that `exp` is in the future, `nbf` is in the past, and `iat` is not in the future,
+/// each with `leeway` seconds of allowed clock skew. Claims that aren't present aren't checked.
+/// Returns the names of the checked claims, or every failed check
+pub(crate) fn validate_time_claims(
+    claims: &serde_json::Value,
+    now: i64,
+    leeway: u64,
+) -> Result<Vec<&'static str>, Vec<String>> {
+    let mut checked = Vec::new();
+    let mut failures = Vec::new();
+    let now = now as f64;
+    let leeway = leeway as f64;
+    for name in ["exp", "nbf", "iat"] {
+        let Some(value) = claims.get(name) else {
+            continue;
+        };
+        let Some(at) = value.as_f64() else {
+            failures.push(format!("'{name}' is not a NumericDate: '{value}'"));
+            continue;
+        };
+        checked.push(name);
+        match name {
+            "exp" if now >= at + leeway => failures.push(format!(
+                "The token expired at {value}, {} seconds ago (leeway {leeway}s)",
+                now - at
+            )),
+            "nbf" if now + leeway < at => failures.push(format!(
+                "The token is not valid before {value}, in {} seconds (leeway {leeway}s)",
+                at - now
+            )),
+            "iat" if now + leeway < at => failures.push(format!(
+                "The token was issued in the future at {value}, in {} seconds (leeway {leeway}s)",
+                at - now
+            )),
+            _ => {}
+        }
+    }
+    if failures.is_empty() {
+        Ok(checked)
+    } else {
+        Err(failures)
+    }
+}
+
+/// Expected claim values, every mismatch is reported
+pub(crate) struct ClaimExpectations {
+    iss: Option<String>,
+    aud: Option<String>,
+    sub: Option<String>,
+    azp: Option<String>,
+    /// JSON pointers and the values expected at them
+    claims: Vec<(String, serde_json::Value)>,
+}
+
+impl ClaimExpectations {
+    /// Claims are `key=value`, where the key is a claim name or a JSON pointer (`/a/b`),
+    /// and the value is compared as JSON if it parses as JSON, otherwise as a string
+    pub(crate) fn parse(
+        iss: Option<String>,
+        aud: Option<String>,
+        sub: Option<String>,
+        azp: Option<String>,
+        claims: Vec<String>,
+    ) -> Result<Self, String> {
+        let claims = claims
+            .into_iter()
+            .map(|claim| {
+                let (key, value) = claim.split_once('=').ok_or_else(|| {
+                    format!("Expected claim was not in the form key=value: '{claim}'")
+                })?;
+                let pointer = if key.starts_with('/') {
+                    key.to_string()
+                } else {
+                    // RFC-6901 escaping of a single reference token
+                    format!("/{}", key.replace('~', "~0").replace('/', "~1"))
+                };
+                let value = serde_json::from_str(value)
+                    .unwrap_or_else(|_e| serde_json::Value::String(value.to_string()));
+                Ok((pointer, value))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self {
+            iss,
+            aud,
+            sub,
+            azp,
+            claims,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.iss.is_none()
+            && self.aud.is_none()
+            && self.sub.is_none()
+            && self.azp.is_none()
+            && self.claims.is_empty()
+    }
+
+    /// Returns the names of the checked claims, or every mismatch
+    pub(crate) fn check(&self, claims: &serde_json::Value) -> Result<Vec<String>, Vec<String>> {
+        let mut checked = Vec::new();
+        let mut failures = Vec::new();
+        for (name, expected) in [("iss", &self.iss), ("sub", &self.sub), ("azp", &self.azp)] {
+            let Some(expected) = expected else {
+                continue;
+            };
+            checked.push(name.to_string());
+            match claims.get(name) {
+                Some(serde_json::Value::String(actual)) if actual == expected => {}
+                Some(actual) => {
+                    failures.push(format!("'{name}' is {actual}, expected '{expected}'"));
+                }
+                None => failures.push(format!("'{name}' is missing, expected '{expected}'")),
+            }
+        }
+        if let Some(expected) = &self.aud {
+            checked.push("aud".to_string());
+            // [RFC-7519](https://datatracker.ietf.org/doc/html/rfc7519#section-4.1.3)
+            // A single audience may be a string instead of an array
+            let contained = match claims.get("aud") {
+                Some(serde_json::Value::String(aud)) => Some(aud == expected),
+                Some(serde_json::Value::Array(auds)) => {
+                    Some(auds.iter().any(|aud| aud.as_str() == Some(expected)))
+                }
+                Some(_) | None => None,
+            };
+            match (contained, claims.get("aud")) {
+                (Some(true), _) => {}
+                (_, Some(actual)) => {
+                    failures.push(format!(
+                        "'aud' is {actual}, expected it to contain '{expected}'"
+                    ));
+                }
+                (_, None) => failures.push(format!("'aud' is missing, expected '{expected}'")),
+            }
+        }
+        for (pointer, expected) in &self.claims {
+            checked.push(pointer.clone());
+            match claims.pointer(pointer) {
+                Some(actual) if actual == expected => {}
+                Some(actual) => {
+                    failures.push(format!("'{pointer}' is {actual}, expected {expected}"));
+                }
+                None => failures.push(format!("'{pointer}' is missing, expected {expected}")),
+            }
+        }
+        if failures.is_empty() {
+            Ok(checked)
+        } else {
+            Err(failures)
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use alloc::format;
+use alloc::string::String;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tiny_std::time::SystemTime;
+
+/// Where "now" comes from when formatting relative times and validating claims
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum Clock {
+    System,
+    /// Pinned to seconds since the unix epoch
+    Fixed(i64),
+}
+
+impl Clock {
+    /// Accepts seconds since the unix epoch, or an RFC 3339 timestamp
+    pub(crate) fn parse(at: Option<&str>) -> Result<Self, String> {
+        let Some(at) = at else {
+            return Ok(Self::System);
+        };
+        if let Ok(seconds) = at.parse::<i64>() {
+            return Ok(Self::Fixed(seconds));
+        }
+        OffsetDateTime::parse(at, &Rfc3339)
+            .map(|odt| Self::Fixed(odt.unix_timestamp()))
+            .map_err(|e| {
+                format!("Failed to parse '{at}' as unix seconds or an RFC 3339 timestamp: {e}")
+            })
+    }
+
+    /// Seconds since the unix epoch
+    pub(crate) fn now(self) -> i64 {
+        match self {
+            Clock::System => SystemTime::now().duration_since_unix_time().as_secs() as i64,
+            Clock::Fixed(seconds) => seconds,
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::cbor::Cbor;
+use crate::jwt::{JwtPayload, TokenKind, ValidatedOutput};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use base64::Engine;
+
+/// [RFC-8392](https://datatracker.ietf.org/doc/html/rfc8392#section-6)
+const CWT_TAG: u64 = 61;
+/// [RFC-9052](https://datatracker.ietf.org/doc/html/rfc9052#section-2)
+const COSE_SIGN1_TAG: u64 = 18;
+const COSE_MAC0_TAG: u64 = 17;
+
+/// [RFC-9052](https://datatracker.ietf.org/doc/html/rfc9052#section-2)
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CoseStructure {
+    Sign1,
+    Mac0,
+    /// Without a tag the two can't be told apart, they have the same shape
+    Untagged,
+}
+
+impl CoseStructure {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            CoseStructure::Sign1 => "COSE_Sign1",
+            CoseStructure::Mac0 => "COSE_Mac0",
+            CoseStructure::Untagged => "untagged COSE_Sign1 or COSE_Mac0",
+        }
+    }
+}
+
+/// [RFC-9052](https://datatracker.ietf.org/doc/html/rfc9052#section-4.2)
+pub struct CoseParts {
+    pub structure: CoseStructure,
+    pub protected: serde_json::Value,
+    pub unprotected: serde_json::Value,
+    /// The signature of a `COSE_Sign1`, or the tag of a `COSE_Mac0`
+    pub signature: Vec<u8>,
+}
+
+/// The token as hex, base64 or base64url, or the raw bytes
+pub(crate) fn decode_input(raw: &[u8]) -> Vec<u8> {
+    let Ok(text) = core::str::from_utf8(raw) else {
+        return raw.to_vec();
+    };
+    let text = text.trim();
+    if !text.is_empty() && text.len() % 2 == 0 && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return (0..text.len())
+            .step_by(2)
+            .filter_map(|ind| u8::from_str_radix(&text[ind..ind + 2], 16).ok())
+            .collect();
+    }
+    for engine in [
+        &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+        &base64::engine::general_purpose::URL_SAFE,
+        &base64::engine::general_purpose::STANDARD,
+        &base64::engine::general_purpose::STANDARD_NO_PAD,
+    ] {
+        if let Ok(decoded) = engine.decode(text) {
+            return decoded;
+        }
+    }
+    raw.to_vec()
+}
+
+/// [RFC-8392](https://datatracker.ietf.org/doc/html/rfc8392#section-7.2)
+/// The claims are decoded, but the signature or MAC isn't checked
+pub(crate) fn validate_cwt(raw: &[u8]) -> Result<ValidatedOutput, String> {
+    let mut item = Cbor::decode(raw).map_err(|e| format!("Failed to decode CWT as CBOR: {e}"))?;
+    let mut structure = CoseStructure::Untagged;
+    loop {
+        match item {
+            Cbor::Tag(CWT_TAG, inner) => item = *inner,
+            Cbor::Tag(COSE_SIGN1_TAG, inner) => {
+                structure = CoseStructure::Sign1;
+                item = *inner;
+                break;
+            }
+            Cbor::Tag(COSE_MAC0_TAG, inner) => {
+                structure = CoseStructure::Mac0;
+                item = *inner;
+                break;
+            }
+            Cbor::Tag(tag, _) => {
+                return Err(format!(
+                    "Unsupported CBOR tag {tag} on CWT, expected COSE_Sign1 ({COSE_SIGN1_TAG}) or COSE_Mac0 ({COSE_MAC0_TAG})"
+                ))
+            }
+            _ => break,
+        }
+    }
+    let Cbor::Array(parts) = item else {
+        return Err(format!(
+            "{} was not a CBOR array: '{}'",
+            structure.name(),
+            item.to_json()
+        ));
+    };
+    let [raw_protected, unprotected, payload, signature] =
+        <[Cbor; 4]>::try_from(parts).map_err(|parts| {
+            format!(
+                "{} did not have 4 elements, had {}",
+                structure.name(),
+                parts.len()
+            )
+        })?;
+    let Cbor::Bytes(raw_protected) = raw_protected else {
+        return Err("The COSE protected header was not a byte string".to_string());
+    };
+    let protected = if raw_protected.is_empty() {
+        serde_json::Map::new()
+    } else {
+        header_map(
+            &Cbor::decode(&raw_protected)
+                .map_err(|e| format!("Failed to decode COSE protected header: {e}"))?,
+        )
+        .ok_or_else(|| "The COSE protected header was not a map".to_string())?
+    };
+    let unprotected = header_map(&unprotected)
+        .ok_or_else(|| "The COSE unprotected header was not a map".to_string())?;
+    let mut header = protected.clone();
+    for (name, value) in &unprotected {
+        // The header labels have to be disjoint
+        if header.insert(name.clone(), value.clone()).is_some() {
+            return Err(format!(
+                "Header parameter '{name}' is in both the protected and unprotected header"
+            ));
+        }
+    }
+    let (payload, raw_payload) = match payload {
+        Cbor::Bytes(raw_payload) => (claims(&raw_payload), raw_payload),
+        Cbor::Null => (JwtPayload::Detached, Vec::new()),
+        _ => return Err("The COSE payload was not a byte string or nil".to_string()),
+    };
+    let Cbor::Bytes(signature) = signature else {
+        return Err("The COSE signature or tag was not a byte string".to_string());
+    };
+    let signature_name = if structure == CoseStructure::Mac0 {
+        "tag"
+    } else {
+        "signature"
+    };
+    let segments = [
+        ("protected", raw_protected.as_slice()),
+        ("payload", raw_payload.as_slice()),
+        (signature_name, signature.as_slice()),
+    ]
+    .into_iter()
+    .map(|(name, bytes)| {
+        (
+            name.to_string(),
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes),
+        )
+    })
+    .collect();
+    Ok(ValidatedOutput {
+        header: serde_json::Value::Object(header),
+        payload,
+        kind: TokenKind::Cose(CoseParts {
+            structure,
+            protected: serde_json::Value::Object(protected),
+            unprotected: serde_json::Value::Object(unprotected),
+            signature,
+        }),
+        segments,
+        inflated: None,
+    })
+}
+
+/// [RFC-8392](https://datatracker.ietf.org/doc/html/rfc8392#section-3)
+/// A claims set is a map, the registered integer keys are named like their JWT counterparts
+fn claims(raw: &[u8]) -> JwtPayload {
+    match Cbor::decode(raw) {
+        Ok(Cbor::Map(entries)) => JwtPayload::Json(serde_json::Value::Object(
+            entries
+                .iter()
+                .map(|(key, value)| {
+                    let name = key
+                        .as_i64()
+                        .and_then(claim_name)
+                        .map_or_else(|| key.to_json_key(), ToString::to_string);
+                    (name, value.to_json())
+                })
+                .collect(),
+        )),
+        _ => JwtPayload::from_bytes(raw.to_vec()),
+    }
+}
+
+/// [RFC-8392](https://datatracker.ietf.org/doc/html/rfc8392#section-4)
+fn claim_name(key: i64) -> Option<&'static str> {
+    match key {
+        1 => Some("iss"),
+        2 => Some("sub"),
+        3 => Some("aud"),
+        4 => Some("exp"),
+        5 => Some("nbf"),
+        6 => Some("iat"),
+        7 => Some("cti"),
+        // [RFC-8747](https://datatracker.ietf.org/doc/html/rfc8747#section-3.1)
+        8 => Some("cnf"),
+        _ => None,
+    }
+}
+
+/// [RFC-9052](https://datatracker.ietf.org/doc/html/rfc9052#section-3.1)
+/// The common header labels are named, and so are the algorithms
+fn header_map(header: &Cbor) -> Option<serde_json::Map<String, serde_json::Value>> {
+    let Cbor::Map(entries) = header else {
+        return None;
+    };
+    Some(
+        entries
+            .iter()
+            .map(|(label, value)| match label.as_i64() {
+                Some(1) => (
+                    "alg".to_string(),
+                    value
+                        .as_i64()
+                        .and_then(alg_name)
+                        .map_or_else(|| value.to_json(), Into::into),
+                ),
+                Some(2) => ("crit".to_string(), value.to_json()),
+                Some(3) => ("content type".to_string(), value.to_json()),
+                Some(4) => ("kid".to_string(), value.to_json()),
+                Some(5) => ("IV".to_string(), value.to_json()),
+                Some(6) => ("Partial IV".to_string(), value.to_json()),
+                _ => (label.to_json_key(), value.to_json()),
+            })
+            .collect(),
+    )
+}
+
+/// [RFC-9053](https://datatracker.ietf.org/doc/html/rfc9053#section-2)
+/// The signature algorithms share their names with JWA
+fn alg_name(alg: i64) -> Option<&'static str> {
+    match alg {
+        -7 => Some("ES256"),
+        -35 => Some("ES384"),
+        -36 => Some("ES512"),
+        -8 => Some("EdDSA"),
+        -37 => Some("PS256"),
+        -38 => Some("PS384"),
+        -39 => Some("PS512"),
+        -257 => Some("RS256"),
+        -258 => Some("RS384"),
+        -259 => Some("RS512"),
+        4 => Some("HMAC 256/64"),
+        5 => Some("HMAC 256/256"),
+        6 => Some("HMAC 384/384"),
+        7 => Some("HMAC 512/512"),
+        14 => Some("AES-MAC 128/64"),
+        15 => Some("AES-MAC 256/64"),
+        25 => Some("AES-MAC 128/128"),
+        26 => Some("AES-MAC 256/128"),
+        _ => None,
+    }
+}
@@ -0,0 +1,336 @@
+use crate::jwk::{ec_public_key_sec1, parse_private_jwk};
+use crate::jwt::{try_decode_b64, JweParts};
+use aes_gcm::aead::{Aead, Nonce, Payload};
+use aes_gcm::{Aes128Gcm, Aes256Gcm, KeyInit};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use cbc::cipher::block_padding::Pkcs7;
+use cbc::cipher::{BlockCipher, BlockDecryptMut, KeyIvInit};
+use hmac::{Hmac, Mac};
+use pkcs8::{DecodePrivateKey, Document};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::{Oaep, RsaPrivateKey};
+use sha2::{Digest, Sha256, Sha512};
+use tiny_std::UnixStr;
+
+/// Key material used to decrypt a JWE
+pub(crate) enum DecryptionKey {
+    /// The content encryption key for `dir`, or the key encryption key for `A128KW` and `A256KW`
+    Symmetric(Vec<u8>),
+    /// Private key for `RSA-OAEP` and `RSA-OAEP-256`
+    Rsa(Box<RsaPrivateKey>),
+    /// Private keys for the `ECDH-ES` family
+    P256(p256::SecretKey),
+    P384(p384::SecretKey),
+    P521(p521::SecretKey),
+}
+
+impl DecryptionKey {
+    /// Accepts a JWK, or a PEM or DER encoded private key, either as `PKCS#8`, as a `PKCS#1`
+    /// `RSAPrivateKey`, or as a `SEC1` `ECPrivateKey`
+    pub(crate) fn load(path: &UnixStr) -> Result<Self, String> {
+        let raw = tiny_std::fs::read(path)
+            .map_err(|e| format!("Failed to read decryption key from path: {path:?}, e: {e}"))?;
+        let text = core::str::from_utf8(&raw).ok().map(str::trim);
+        if let Some(jwk) = text.filter(|s| s.starts_with('{')) {
+            let jwk: serde_json::Value = serde_json::from_str(jwk)
+                .map_err(|e| format!("Failed to deserialize decryption key as a JWK: {e}"))?;
+            return parse_private_jwk(&jwk);
+        }
+        if let Some(pem) = text.filter(|s| s.starts_with("-----BEGIN")) {
+            let (label, der) = Document::from_pem(pem)
+                .map_err(|e| format!("Failed to parse decryption key as PEM: {e}"))?;
+            return match label {
+                "RSA PRIVATE KEY" => RsaPrivateKey::from_pkcs1_der(der.as_bytes())
+                    .map(|key| Self::Rsa(Box::new(key)))
+                    .map_err(|e| format!("Failed to parse PEM key as an RSA private key: {e}")),
+                "EC PRIVATE KEY" => Self::parse_sec1_der(der.as_bytes()),
+                _ => Self::parse_pkcs8_der(der.as_bytes()),
+            };
+        }
+        Self::parse_pkcs8_der(&raw)
+            .or_else(|_e| Self::parse_sec1_der(&raw))
+            .or_else(|_e| RsaPrivateKey::from_pkcs1_der(&raw).map(|key| Self::Rsa(Box::new(key))))
+            .map_err(|_e| {
+                "Failed to parse decryption key as a JWK, or as a PEM or DER encoded private key"
+                    .to_string()
+            })
+    }
+
+    fn parse_pkcs8_der(der: &[u8]) -> Result<Self, String> {
+        if let Ok(key) = RsaPrivateKey::from_pkcs8_der(der) {
+            return Ok(Self::Rsa(Box::new(key)));
+        }
+        if let Ok(key) = p256::SecretKey::from_pkcs8_der(der) {
+            return Ok(Self::P256(key));
+        }
+        if let Ok(key) = p384::SecretKey::from_pkcs8_der(der) {
+            return Ok(Self::P384(key));
+        }
+        p521::SecretKey::from_pkcs8_der(der)
+            .map(Self::P521)
+            .map_err(|_e| {
+                "Failed to parse key as an RSA, P-256, P-384, or P-521 private key".to_string()
+            })
+    }
+
+    fn parse_sec1_der(der: &[u8]) -> Result<Self, String> {
+        if let Ok(key) = p256::SecretKey::from_sec1_der(der) {
+            return Ok(Self::P256(key));
+        }
+        if let Ok(key) = p384::SecretKey::from_sec1_der(der) {
+            return Ok(Self::P384(key));
+        }
+        p521::SecretKey::from_sec1_der(der)
+            .map(Self::P521)
+            .map_err(|_e| "Failed to parse key as a P-256, P-384, or P-521 private key".to_string())
+    }
+}
+
+/// [RFC-7516](https://datatracker.ietf.org/doc/html/rfc7516#section-5.2)
+pub(crate) fn decrypt(
+    header: &serde_json::Value,
+    parts: &JweParts,
+    key: &DecryptionKey,
+) -> Result<Vec<u8>, String> {
+    // Presence and ascii-ness is checked when validating the header
+    let alg = header_str(header, "alg")?;
+    let enc = header_str(header, "enc")?;
+    let cek_len = content_key_len(enc)?;
+    let cek = match (alg, key) {
+        ("dir", DecryptionKey::Symmetric(cek)) => {
+            if !parts.encrypted_key.is_empty() {
+                return Err("A JWE using 'dir' should have an empty encrypted key".to_string());
+            }
+            cek.clone()
+        }
+        ("A128KW" | "A256KW", DecryptionKey::Symmetric(kek)) => {
+            aes_key_unwrap(alg, kek, &parts.encrypted_key)?
+        }
+        ("RSA-OAEP", DecryptionKey::Rsa(key)) => key
+            .decrypt(Oaep::new::<sha1::Sha1>(), &parts.encrypted_key)
+            .map_err(|e| format!("Failed to decrypt the content encryption key: {e}"))?,
+        ("RSA-OAEP-256", DecryptionKey::Rsa(key)) => key
+            .decrypt(Oaep::new::<Sha256>(), &parts.encrypted_key)
+            .map_err(|e| format!("Failed to decrypt the content encryption key: {e}"))?,
+        ("ECDH-ES", _) => {
+            if !parts.encrypted_key.is_empty() {
+                return Err("A JWE using 'ECDH-ES' should have an empty encrypted key".to_string());
+            }
+            ecdh_es_derive(header, key, enc, cek_len)?
+        }
+        ("ECDH-ES+A128KW", _) => {
+            let kek = ecdh_es_derive(header, key, alg, 16)?;
+            aes_key_unwrap("A128KW", &kek, &parts.encrypted_key)?
+        }
+        ("ECDH-ES+A256KW", _) => {
+            let kek = ecdh_es_derive(header, key, alg, 32)?;
+            aes_key_unwrap("A256KW", &kek, &parts.encrypted_key)?
+        }
+        (unk, _) => {
+            return Err(format!(
+                "Unsupported alg for decryption with the supplied key: '{unk}'"
+            ))
+        }
+    };
+    if cek.len() != cek_len {
+        return Err(format!(
+            "Content encryption key has length {}, {enc} requires {cek_len}",
+            cek.len()
+        ));
+    }
+    decrypt_content(enc, &cek, parts)
+}
+
+fn header_str<'a>(header: &'a serde_json::Value, path: &'static str) -> Result<&'a str, String> {
+    header
+        .get(path)
+        .and_then(serde_json::Value::as_str)
+        .ok_or_else(|| format!("Header did not contain a '{path}'-string: '{header}'"))
+}
+
+/// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-5.1)
+fn content_key_len(enc: &str) -> Result<usize, String> {
+    match enc {
+        "A128GCM" => Ok(16),
+        "A256GCM" | "A128CBC-HS256" => Ok(32),
+        "A256CBC-HS512" => Ok(64),
+        unk => Err(format!("Unsupported enc: '{unk}'")),
+    }
+}
+
+/// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-4.4)
+fn aes_key_unwrap(alg: &str, kek: &[u8], wrapped: &[u8]) -> Result<Vec<u8>, String> {
+    let unwrapped = match (alg, kek.len()) {
+        ("A128KW", 16) => aes_kw::KekAes128::try_from(kek).and_then(|kek| kek.unwrap_vec(wrapped)),
+        ("A256KW", 32) => aes_kw::KekAes256::try_from(kek).and_then(|kek| kek.unwrap_vec(wrapped)),
+        (alg, len) => {
+            return Err(format!(
+                "Key encryption key has length {len}, which can't be used for {alg}"
+            ))
+        }
+    };
+    unwrapped.map_err(|e| format!("Failed to unwrap the content encryption key: {e}"))
+}
+
+/// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-4.6)
+fn ecdh_es_derive(
+    header: &serde_json::Value,
+    key: &DecryptionKey,
+    algorithm_id: &str,
+    key_len: usize,
+) -> Result<Vec<u8>, String> {
+    let epk = header
+        .get("epk")
+        .ok_or_else(|| format!("Header did not contain an 'epk'-field: '{header}'"))?;
+    let (crv, sec1) = ec_public_key_sec1(epk)?;
+    let shared_secret = match (crv.as_str(), key) {
+        ("P-256", DecryptionKey::P256(key)) => {
+            let epk = p256::PublicKey::from_sec1_bytes(&sec1)
+                .map_err(|e| format!("Invalid P-256 ephemeral public key: {e}"))?;
+            p256::ecdh::diffie_hellman(key.to_nonzero_scalar(), epk.as_affine())
+                .raw_secret_bytes()
+                .to_vec()
+        }
+        ("P-384", DecryptionKey::P384(key)) => {
+            let epk = p384::PublicKey::from_sec1_bytes(&sec1)
+                .map_err(|e| format!("Invalid P-384 ephemeral public key: {e}"))?;
+            p384::ecdh::diffie_hellman(key.to_nonzero_scalar(), epk.as_affine())
+                .raw_secret_bytes()
+                .to_vec()
+        }
+        ("P-521", DecryptionKey::P521(key)) => {
+            let epk = p521::PublicKey::from_sec1_bytes(&sec1)
+                .map_err(|e| format!("Invalid P-521 ephemeral public key: {e}"))?;
+            p521::ecdh::diffie_hellman(key.to_nonzero_scalar(), epk.as_affine())
+                .raw_secret_bytes()
+                .to_vec()
+        }
+        (crv, _) => {
+            return Err(format!(
+                "The ephemeral key's curve '{crv}' does not match the supplied decryption key"
+            ))
+        }
+    };
+    let apu = optional_b64(header, "apu")?;
+    let apv = optional_b64(header, "apv")?;
+    Ok(concat_kdf(
+        &shared_secret,
+        algorithm_id.as_bytes(),
+        &apu,
+        &apv,
+        key_len,
+    ))
+}
+
+fn optional_b64(header: &serde_json::Value, path: &'static str) -> Result<Vec<u8>, String> {
+    match header.get(path) {
+        None => Ok(Vec::new()),
+        Some(serde_json::Value::String(encoded)) => try_decode_b64(encoded),
+        Some(other) => Err(format!("Header field '{path}' was not a string: '{other}'")),
+    }
+}
+
+/// [NIST.800-56A](https://datatracker.ietf.org/doc/html/rfc7518#section-4.6.2), with SHA-256
+fn concat_kdf(
+    shared_secret: &[u8],
+    algorithm_id: &[u8],
+    apu: &[u8],
+    apv: &[u8],
+    key_len: usize,
+) -> Vec<u8> {
+    let mut derived = Vec::with_capacity(key_len + 32);
+    let mut round = 1u32;
+    while derived.len() < key_len {
+        let mut hasher = Sha256::new();
+        hasher.update(round.to_be_bytes());
+        hasher.update(shared_secret);
+        for data in [algorithm_id, apu, apv] {
+            hasher.update((data.len() as u32).to_be_bytes());
+            hasher.update(data);
+        }
+        hasher.update(((key_len * 8) as u32).to_be_bytes());
+        derived.extend_from_slice(&hasher.finalize());
+        round += 1;
+    }
+    derived.truncate(key_len);
+    derived
+}
+
+/// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-5)
+fn decrypt_content(enc: &str, cek: &[u8], parts: &JweParts) -> Result<Vec<u8>, String> {
+    let aad = parts.protected_header.as_bytes();
+    match enc {
+        "A128GCM" => decrypt_gcm::<Aes128Gcm>(cek, aad, parts),
+        "A256GCM" => decrypt_gcm::<Aes256Gcm>(cek, aad, parts),
+        "A128CBC-HS256" => decrypt_cbc_hmac::<aes::Aes128, Hmac<Sha256>>(cek, aad, parts),
+        "A256CBC-HS512" => decrypt_cbc_hmac::<aes::Aes256, Hmac<Sha512>>(cek, aad, parts),
+        unk => Err(format!("Unsupported enc: '{unk}'")),
+    }
+}
+
+/// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-5.2.2.2)
+/// The first half of the key is the MAC key, the second half the encryption key, and the tag is
+/// the left half of `HMAC(AAD || IV || ciphertext || AL)`
+fn decrypt_cbc_hmac<C, M>(cek: &[u8], aad: &[u8], parts: &JweParts) -> Result<Vec<u8>, String>
+where
+    C: BlockDecryptMut + BlockCipher,
+    cbc::Decryptor<C>: KeyIvInit,
+    M: Mac + KeyInit,
+{
+    let (mac_key, enc_key) = cek.split_at(cek.len() / 2);
+    // A truncated tag would be compared only by what's left of it
+    if parts.tag.len() != mac_key.len() {
+        return Err(format!(
+            "Failed to decrypt content, expected an authentication tag of {} bytes, got {}",
+            mac_key.len(),
+            parts.tag.len()
+        ));
+    }
+    let mut mac = <M as KeyInit>::new_from_slice(mac_key)
+        .map_err(|_e| "Failed to initialize hmac from the content encryption key".to_string())?;
+    mac.update(aad);
+    mac.update(&parts.iv);
+    mac.update(&parts.ciphertext);
+    mac.update(&((aad.len() as u64) * 8).to_be_bytes());
+    mac.verify_truncated_left(&parts.tag)
+        .map_err(|_e| "Failed to decrypt content, authentication tag mismatch".to_string())?;
+    cbc::Decryptor::<C>::new_from_slices(enc_key, &parts.iv)
+        .map_err(|_e| format!("Invalid iv length {}", parts.iv.len()))?
+        .decrypt_padded_vec_mut::<Pkcs7>(&parts.ciphertext)
+        .map_err(|_e| "Failed to decrypt content, bad padding".to_string())
+}
+
+/// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-5.3)
+fn decrypt_gcm<C: Aead + KeyInit>(
+    cek: &[u8],
+    aad: &[u8],
+    parts: &JweParts,
+) -> Result<Vec<u8>, String> {
+    if parts.iv.len() != 12 {
+        return Err(format!(
+            "Invalid iv length {}, AES GCM requires 12",
+            parts.iv.len()
+        ));
+    }
+    // The tag is split off the end of the message, a short one would take from the ciphertext
+    if parts.tag.len() != 16 {
+        return Err(format!(
+            "Failed to decrypt content, expected an authentication tag of 16 bytes, got {}",
+            parts.tag.len()
+        ));
+    }
+    let cipher = C::new_from_slice(cek)
+        .map_err(|_e| format!("Invalid content encryption key length {}", cek.len()))?;
+    let mut msg = Vec::with_capacity(parts.ciphertext.len() + parts.tag.len());
+    msg.extend_from_slice(&parts.ciphertext);
+    msg.extend_from_slice(&parts.tag);
+    cipher
+        .decrypt(
+            Nonce::<C>::from_slice(&parts.iv),
+            Payload { msg: &msg, aad },
+        )
+        .map_err(|_e| "Failed to decrypt content, authentication tag mismatch".to_string())
+}
@@ -0,0 +1,260 @@
+use crate::jwe::DecryptionKey;
+use crate::jwt::try_decode_b64;
+use crate::verify::VerificationKey;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use rsa::{BigUint, RsaPrivateKey, RsaPublicKey};
+
+/// A single key from a JWKS, keys that can't be used for verification are kept so that they
+/// can be listed when no key matches.
+struct Jwk {
+    kid: Option<String>,
+    alg: Option<String>,
+    key_use: Option<String>,
+    key: Result<VerificationKey, String>,
+}
+
+/// [RFC-7517](https://datatracker.ietf.org/doc/html/rfc7517#section-5)
+pub(crate) struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+impl JwkSet {
+    pub(crate) fn parse(raw: &str) -> Result<Self, String> {
+        let json: serde_json::Value = serde_json::from_str(raw)
+            .map_err(|e| format!("Failed to deserialize JWKS, not valid JSON: {e}"))?;
+        let keys = json
+            .get("keys")
+            .and_then(serde_json::Value::as_array)
+            .ok_or_else(|| "JWKS did not contain a 'keys'-array".to_string())?;
+        let keys = keys.iter().map(Jwk::parse).collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { keys })
+    }
+
+    /// [RFC-7515](https://datatracker.ietf.org/doc/html/rfc7515#section-4.1.4)
+    /// If the token has a `kid` the key has to have the same `kid`, if it doesn't, there has to be
+    /// exactly one key that can verify the alg.
+    /// Returns the key, and its `kid` if it has one
+    pub(crate) fn select(
+        &self,
+        kid: Option<&str>,
+        alg: &str,
+    ) -> Result<(&VerificationKey, Option<&str>), String> {
+        let matching = self
+            .keys
+            .iter()
+            .filter(|jwk| {
+                kid.is_none_or(|kid| jwk.kid.as_deref() == Some(kid))
+                    && jwk.alg.as_deref().is_none_or(|key_alg| key_alg == alg)
+                    && jwk
+                        .key_use
+                        .as_deref()
+                        .is_none_or(|key_use| key_use == "sig")
+            })
+            .collect::<Vec<_>>();
+        let usable = matching
+            .iter()
+            .filter_map(|jwk| {
+                let key = jwk.key.as_ref().ok().filter(|key| key.supports(alg))?;
+                Some((key, jwk.kid.as_deref()))
+            })
+            .collect::<Vec<_>>();
+        match (usable.as_slice(), matching.as_slice()) {
+            ([selected], _) => Ok(*selected),
+            // `kid`s are supposed to be unique, if they aren't, take the first that fits
+            ([selected, ..], _) if kid.is_some() => Ok(*selected),
+            ([], [jwk]) => match &jwk.key {
+                Ok(_) => Err(format!(
+                    "Key {} in the JWKS can't be used to verify an {alg} signature",
+                    jwk.display_kid()
+                )),
+                Err(e) => Err(format!(
+                    "Key {} in the JWKS can't be used for verification: {e}",
+                    jwk.display_kid()
+                )),
+            },
+            ([], _) => Err(format!(
+                "No key in the JWKS matches kid={} alg={alg}, available kids: [{}]",
+                kid.unwrap_or("<none>"),
+                self.available_kids()
+            )),
+            _ => Err(format!(
+                "The token has no 'kid' and more than one key in the JWKS can verify {alg}, available kids: [{}]",
+                self.available_kids()
+            )),
+        }
+    }
+
+    fn available_kids(&self) -> String {
+        let mut kids = String::new();
+        for (ind, jwk) in self.keys.iter().enumerate() {
+            if ind != 0 {
+                kids.push_str(", ");
+            }
+            kids.push_str(&jwk.display_kid());
+        }
+        kids
+    }
+}
+
+impl Jwk {
+    fn display_kid(&self) -> String {
+        match &self.kid {
+            Some(kid) => format!("'{kid}'"),
+            None => "<no kid>".to_string(),
+        }
+    }
+
+    fn parse(raw: &serde_json::Value) -> Result<Self, String> {
+        let kid = opt_str(raw, "kid")?;
+        let alg = opt_str(raw, "alg")?;
+        let key_use = opt_str(raw, "use")?;
+        let kty = opt_str(raw, "kty")?
+            .ok_or_else(|| format!("JWK did not contain a 'kty'-field: '{raw}'"))?;
+        let key = match kty.as_str() {
+            "RSA" => parse_rsa(raw),
+            "EC" => parse_ec(raw),
+            "OKP" => parse_okp(raw),
+            "oct" => b64_member(raw, "k").map(VerificationKey::Hmac),
+            unk => Err(format!("Unsupported kty '{unk}'")),
+        };
+        Ok(Self {
+            kid,
+            alg,
+            key_use,
+            key,
+        })
+    }
+}
+
+fn opt_str(raw: &serde_json::Value, path: &'static str) -> Result<Option<String>, String> {
+    match raw.get(path) {
+        None => Ok(None),
+        Some(serde_json::Value::String(s)) => Ok(Some(s.clone())),
+        Some(other) => Err(format!(
+            "JWK member '{path}' was not a string: '{other}', in: '{raw}'"
+        )),
+    }
+}
+
+fn b64_member(raw: &serde_json::Value, path: &'static str) -> Result<Vec<u8>, String> {
+    let encoded = raw
+        .get(path)
+        .and_then(serde_json::Value::as_str)
+        .ok_or_else(|| format!("Missing '{path}'-member"))?;
+    try_decode_b64(encoded)
+}
+
+/// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-6.3.1)
+fn parse_rsa(raw: &serde_json::Value) -> Result<VerificationKey, String> {
+    let n = b64_member(raw, "n")?;
+    let e = b64_member(raw, "e")?;
+    RsaPublicKey::new(BigUint::from_bytes_be(&n), BigUint::from_bytes_be(&e))
+        .map(VerificationKey::Rsa)
+        .map_err(|e| format!("Invalid RSA public key: {e}"))
+}
+
+/// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-6.2.1)
+/// Returns the curve and the public key as an uncompressed SEC1 point. Each coordinate must be
+/// the curve's full size, a short `x` could otherwise be made up for by a long `y`
+pub(crate) fn ec_public_key_sec1(raw: &serde_json::Value) -> Result<(String, Vec<u8>), String> {
+    let crv = opt_str(raw, "crv")?.ok_or_else(|| "Missing 'crv'-member".to_string())?;
+    let coordinate_len = match crv.as_str() {
+        "P-256" => 32,
+        "P-384" => 48,
+        "P-521" => 66,
+        unk => return Err(format!("Unsupported EC crv '{unk}'")),
+    };
+    let x = b64_member(raw, "x")?;
+    let y = b64_member(raw, "y")?;
+    if x.len() != coordinate_len || y.len() != coordinate_len {
+        return Err(format!(
+            "Invalid {crv} public key, expected coordinates of {coordinate_len} bytes, got x: {}, y: {}",
+            x.len(),
+            y.len()
+        ));
+    }
+    let mut sec1 = Vec::with_capacity(1 + x.len() + y.len());
+    sec1.push(0x04);
+    sec1.extend_from_slice(&x);
+    sec1.extend_from_slice(&y);
+    Ok((crv, sec1))
+}
+
+fn parse_ec(raw: &serde_json::Value) -> Result<VerificationKey, String> {
+    let (crv, sec1) = ec_public_key_sec1(raw)?;
+    match crv.as_str() {
+        "P-256" => p256::ecdsa::VerifyingKey::from_sec1_bytes(&sec1)
+            .map(VerificationKey::P256)
+            .map_err(|e| format!("Invalid P-256 public key: {e}")),
+        "P-384" => p384::ecdsa::VerifyingKey::from_sec1_bytes(&sec1)
+            .map(VerificationKey::P384)
+            .map_err(|e| format!("Invalid P-384 public key: {e}")),
+        "P-521" => p521::ecdsa::VerifyingKey::from_sec1_bytes(&sec1)
+            .map(VerificationKey::P521)
+            .map_err(|e| format!("Invalid P-521 public key: {e}")),
+        unk => Err(format!("Unsupported EC crv '{unk}'")),
+    }
+}
+
+/// [RFC-8037](https://datatracker.ietf.org/doc/html/rfc8037#section-2)
+fn parse_okp(raw: &serde_json::Value) -> Result<VerificationKey, String> {
+    let crv = opt_str(raw, "crv")?.ok_or_else(|| "Missing 'crv'-member".to_string())?;
+    let x = b64_member(raw, "x")?;
+    match crv.as_str() {
+        "Ed25519" => {
+            let bytes = x
+                .as_slice()
+                .try_into()
+                .map_err(|_e| format!("Invalid Ed25519 public key length {}", x.len()))?;
+            ed25519_dalek::VerifyingKey::from_bytes(bytes)
+                .map(VerificationKey::Ed25519)
+                .map_err(|e| format!("Invalid Ed25519 public key: {e}"))
+        }
+        "Ed448" => {
+            let bytes = x
+                .as_slice()
+                .try_into()
+                .map_err(|_e| format!("Invalid Ed448 public key length {}", x.len()))?;
+            ed448_goldilocks_plus::VerifyingKey::from_bytes(bytes)
+                .map(VerificationKey::Ed448)
+                .map_err(|e| format!("Invalid Ed448 public key: {e}"))
+        }
+        unk => Err(format!("Unsupported OKP crv '{unk}'")),
+    }
+}
+
+/// A private JWK used for decryption, `oct`, `RSA` with all private members, or `EC`
+pub(crate) fn parse_private_jwk(raw: &serde_json::Value) -> Result<DecryptionKey, String> {
+    let kty = opt_str(raw, "kty")?
+        .ok_or_else(|| format!("JWK did not contain a 'kty'-field: '{raw}'"))?;
+    match kty.as_str() {
+        "oct" => b64_member(raw, "k").map(DecryptionKey::Symmetric),
+        "RSA" => {
+            let [n, e, d, p, q] = ["n", "e", "d", "p", "q"]
+                .map(|member| b64_member(raw, member).map(|b| BigUint::from_bytes_be(&b)));
+            RsaPrivateKey::from_components(n?, e?, d?, alloc::vec![p?, q?])
+                .map(|key| DecryptionKey::Rsa(Box::new(key)))
+                .map_err(|e| format!("Invalid RSA private key: {e}"))
+        }
+        "EC" => {
+            let crv = opt_str(raw, "crv")?.ok_or_else(|| "Missing 'crv'-member".to_string())?;
+            let d = b64_member(raw, "d")?;
+            match crv.as_str() {
+                "P-256" => p256::SecretKey::from_slice(&d)
+                    .map(DecryptionKey::P256)
+                    .map_err(|e| format!("Invalid P-256 private key: {e}")),
+                "P-384" => p384::SecretKey::from_slice(&d)
+                    .map(DecryptionKey::P384)
+                    .map_err(|e| format!("Invalid P-384 private key: {e}")),
+                "P-521" => p521::SecretKey::from_slice(&d)
+                    .map(DecryptionKey::P521)
+                    .map_err(|e| format!("Invalid P-521 private key: {e}")),
+                unk => Err(format!("Unsupported EC crv '{unk}'")),
+            }
+        }
+        unk => Err(format!("Unsupported kty '{unk}' for decryption")),
+    }
+}
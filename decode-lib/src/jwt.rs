@@ -1,3 +1,4 @@
+use crate::cwt::CoseParts;
 use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
@@ -6,18 +7,151 @@ use base64::Engine;
 pub struct ValidatedOutput {
     pub header: serde_json::Value,
     pub payload: JwtPayload,
-    pub _signature: Vec<u8>,
+    pub kind: TokenKind,
+    /// The base64url encoded segments as they appeared in the input, by name, like `header`,
+    /// or `signatures[0].protected` for the JSON serialization.
+    /// For a CWT they're the byte strings of the COSE structure
+    pub segments: Vec<(String, String)>,
+    /// Set if the payload was compressed, see [`inflate_payload`]
+    pub inflated: Option<Inflated>,
 }
 
+pub enum TokenKind {
+    Jws {
+        /// The compact serialization always has exactly one signature
+        signatures: Vec<JwsSignature>,
+        serialization: JwsSerialization,
+    },
+    Jwe(JweParts),
+    /// [RFC-8392](https://datatracker.ietf.org/doc/html/rfc8392)
+    /// A CWT, its header labels and claim keys are named where they're registered
+    Cose(CoseParts),
+}
+
+/// [RFC-7515](https://datatracker.ietf.org/doc/html/rfc7515#section-7)
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum JwsSerialization {
+    Compact,
+    /// General or flattened, the flattened form is treated as general with a single signature
+    Json,
+}
+
+pub struct JwsSignature {
+    /// The union of the protected and unprotected header, which is what the signature is
+    /// checked against
+    pub header: serde_json::Value,
+    pub protected: serde_json::Value,
+    /// Only present in the JSON serialization
+    pub unprotected: Option<serde_json::Value>,
+    pub signature: Vec<u8>,
+    /// The `protected.payload` segments as they appeared in the input, which is what the
+    /// signature is computed over
+    pub signing_input: Vec<u8>,
+}
+
+/// [RFC-7516](https://datatracker.ietf.org/doc/html/rfc7516#section-7.1)
+pub struct JweParts {
+    /// The base64url encoded protected header as it appeared in the input, which is the AAD
+    pub protected_header: String,
+    pub encrypted_key: Vec<u8>,
+    pub iv: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub tag: Vec<u8>,
+}
+
+/// [RFC-7516](https://datatracker.ietf.org/doc/html/rfc7516#section-4.1.3)
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Inflated {
+    pub compressed_len: usize,
+    pub inflated_len: usize,
+}
+
+/// A payload that inflates to more than this is rejected, a few kilobytes of DEFLATE can
+/// expand to gigabytes
+pub const MAX_INFLATED_PAYLOAD: usize = 1024 * 1024;
+
 pub enum JwtPayload {
     Json(serde_json::Value),
     Utf8(String),
-    #[allow(unused)]
     Raw(Vec<u8>),
+    /// The payload of a JWE that hasn't been decrypted
+    Encrypted,
+    /// [RFC-7515](https://datatracker.ietf.org/doc/html/rfc7515#appendix-F)
+    /// Left out of the token, as opposed to an empty payload in the JSON serialization
+    Detached,
+}
+
+impl JwtPayload {
+    pub(crate) fn from_bytes(raw: Vec<u8>) -> Self {
+        match String::from_utf8(raw) {
+            Ok(utf8) => match serde_json::from_str(&utf8) {
+                Ok(json) => JwtPayload::Json(json),
+                Err(_e) => JwtPayload::Utf8(utf8),
+            },
+            Err(e) => JwtPayload::Raw(e.into_bytes()),
+        }
+    }
+}
+
+impl ValidatedOutput {
+    /// [RFC-7515](https://datatracker.ietf.org/doc/html/rfc7515#appendix-F)
+    /// Puts a detached payload back into the token, so that the signatures can be checked.
+    /// Returns the reconstructed compact token if the token is compact serialized, and the
+    /// reconstruction is utf8
+    pub(crate) fn attach_payload(&mut self, detached: &[u8]) -> Result<Option<String>, String> {
+        let TokenKind::Jws {
+            signatures,
+            serialization,
+        } = &mut self.kind
+        else {
+            return Err("Supplied a detached payload, but the token is a JWE".to_string());
+        };
+        if !matches!(self.payload, JwtPayload::Detached) {
+            return Err(
+                "Supplied a detached payload, but the token's payload isn't detached".to_string(),
+            );
+        }
+        // All signatures agree on the encoding, that's checked when validating
+        let encoded = if payload_is_b64(&self.header)? {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(detached)
+                .into_bytes()
+        } else {
+            detached.to_vec()
+        };
+        let mut reconstructed = None;
+        for jws_signature in signatures.iter_mut() {
+            // The signing input is `protected.` with the payload left out
+            jws_signature.signing_input.extend_from_slice(&encoded);
+            if *serialization == JwsSerialization::Compact {
+                let mut token = jws_signature.signing_input.clone();
+                token.push(b'.');
+                token.extend_from_slice(
+                    base64::engine::general_purpose::URL_SAFE_NO_PAD
+                        .encode(&jws_signature.signature)
+                        .as_bytes(),
+                );
+                reconstructed = String::from_utf8(token).ok();
+            }
+        }
+        if let (Some((_, segment)), Ok(encoded)) = (
+            self.segments.iter_mut().find(|(name, _)| name == "payload"),
+            String::from_utf8(encoded),
+        ) {
+            *segment = encoded;
+        }
+        let (detached, inflated) = inflate_payload(&self.header, detached.to_vec())?;
+        self.payload = JwtPayload::from_bytes(detached);
+        self.inflated = inflated;
+        Ok(reconstructed)
+    }
 }
 
 /// [Direct instructions from the RFC](https://datatracker.ietf.org/doc/html/rfc7519#section-7.2)
 pub fn jwt_is_valid(raw: &str) -> Result<ValidatedOutput, String> {
+    if raw.starts_with('{') {
+        return validate_json_serialization(raw);
+    }
     // 1. Contains at least one period
     // 2. Header is the portion before the first period
     let (header, rest) = raw
@@ -35,7 +169,28 @@ pub fn jwt_is_valid(raw: &str) -> Result<ValidatedOutput, String> {
             )
         })?;
     // 5. Verify that the JOSE header contains valid parameters
-    validate_from_header(header_deserialized, rest)
+    validate_from_header(header_deserialized, header, rest)
+}
+
+/// [RFC-7519](https://datatracker.ietf.org/doc/html/rfc7519#section-5.2)
+/// Returns the inner token if the payload is itself a token, either declared through
+/// `cty: JWT`, or a payload that parses as a compact token
+pub(crate) fn nested_token(validated: &ValidatedOutput) -> Result<Option<&str>, String> {
+    let declared = validated
+        .header
+        .get("cty")
+        .and_then(serde_json::Value::as_str)
+        .is_some_and(|cty| cty.eq_ignore_ascii_case("JWT"));
+    match &validated.payload {
+        JwtPayload::Utf8(inner) if declared || jwt_is_valid(inner.trim()).is_ok() => {
+            Ok(Some(inner.trim()))
+        }
+        JwtPayload::Encrypted => Ok(None),
+        _ if declared => {
+            Err("The header declares 'cty: JWT', but the payload is not a token".to_string())
+        }
+        _ => Ok(None),
+    }
 }
 
 fn try_decode_string_value_b64(input: &str) -> Result<String, String> {
@@ -44,7 +199,7 @@ fn try_decode_string_value_b64(input: &str) -> Result<String, String> {
 }
 
 // *Should* as far as I can tell be URL_SAFE_NO_PAD
-fn try_decode_b64(input: &str) -> Result<Vec<u8>, String> {
+pub(crate) fn try_decode_b64(input: &str) -> Result<Vec<u8>, String> {
     match base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(input) {
         Ok(decoded) => Ok(decoded),
         Err(e) => Err(format!(
@@ -54,16 +209,100 @@ fn try_decode_b64(input: &str) -> Result<Vec<u8>, String> {
 }
 
 /// [RFC-7515](https://datatracker.ietf.org/doc/html/rfc7515#section-4)
-fn validate_from_header(raw: serde_json::Value, rest: &str) -> Result<ValidatedOutput, String> {
+fn validate_from_header(
+    raw: serde_json::Value,
+    raw_header: &str,
+    rest: &str,
+) -> Result<ValidatedOutput, String> {
     raw.as_object()
         .ok_or_else(|| format!("Raw json was not a valid object: '{raw}'"))?;
     assert_ascii_at(&raw, "alg")?;
-    let Some(_enc) = raw.get("enc") else {
-        let (body, sig) = rest.split_once('.')
+    // Everything in the compact serialization's header is protected
+    critical_extensions(&raw)?;
+    if raw.get("enc").is_none() {
+        let payload_b64 = payload_is_b64(&raw)?;
+        // An unencoded payload may contain dots, the signature can't
+        let split = if payload_b64 {
+            rest.split_once('.')
+        } else {
+            rest.rsplit_once('.')
+        };
+        let (body, sig) = split
             .ok_or_else(|| format!("Interpreted input as a JWS, but the input did not contain 2 dots, rest of input='{rest}'"))?;
-        return validate_jws(raw, body, sig);
+        return validate_jws(raw, raw_header, body, sig, payload_b64);
+    };
+    assert_ascii_at(&raw, "enc")?;
+    validate_jwe(raw, raw_header, rest)
+}
+
+/// Extensions that can be listed in `crit`, since they're handled when decoding
+const UNDERSTOOD_CRITICAL_EXTENSIONS: &[&str] = &["b64"];
+
+/// Header parameters defined by JWS, JWE, and JWA, which can't be listed in `crit`
+const REGISTERED_HEADER_PARAMETERS: &[&str] = &[
+    "alg", "jku", "jwk", "kid", "x5u", "x5c", "x5t", "x5t#S256", "typ", "cty", "crit", "enc",
+    "zip", "epk", "apu", "apv", "iv", "tag", "p2s", "p2c",
+];
+
+/// [RFC-7515](https://datatracker.ietf.org/doc/html/rfc7515#section-4.1.11)
+/// Returns the critical extensions listed in a protected header, all of which are understood
+pub(crate) fn critical_extensions(header: &serde_json::Value) -> Result<Vec<&str>, String> {
+    let Some(crit) = header.get("crit") else {
+        return Ok(Vec::new());
+    };
+    let crit = crit
+        .as_array()
+        .filter(|crit| !crit.is_empty())
+        .ok_or_else(|| format!("The header's crit-field was not a non-empty array: '{crit}'"))?;
+    let mut extensions: Vec<&str> = Vec::with_capacity(crit.len());
+    for ext in crit {
+        let ext = ext
+            .as_str()
+            .ok_or_else(|| format!("The header's crit-field contained a non-string: '{ext}'"))?;
+        if REGISTERED_HEADER_PARAMETERS.contains(&ext) {
+            return Err(format!(
+                "The header's crit-field lists the registered header parameter '{ext}'"
+            ));
+        }
+        if extensions.contains(&ext) {
+            return Err(format!(
+                "The header's crit-field lists '{ext}' more than once"
+            ));
+        }
+        if header.get(ext).is_none() {
+            return Err(format!(
+                "The header's crit-field lists '{ext}', which is not in the header"
+            ));
+        }
+        if !UNDERSTOOD_CRITICAL_EXTENSIONS.contains(&ext) {
+            return Err(format!(
+                "The header's crit-field lists the unsupported extension '{ext}'"
+            ));
+        }
+        extensions.push(ext);
+    }
+    Ok(extensions)
+}
+
+/// [RFC-7797](https://datatracker.ietf.org/doc/html/rfc7797#section-3)
+/// Whether the payload is base64url encoded, `b64` has to be listed in `crit` if it's present
+fn payload_is_b64(header: &serde_json::Value) -> Result<bool, String> {
+    let Some(b64) = header.get("b64") else {
+        return Ok(true);
     };
-    Err(format!("Got a header that suggests that the supplied JWT is a JWE, this tool only handles JWS, header={raw}"))
+    let b64 = b64
+        .as_bool()
+        .ok_or_else(|| format!("The header's b64-field was not a boolean: '{b64}'"))?;
+    let listed_crit = header
+        .get("crit")
+        .and_then(serde_json::Value::as_array)
+        .is_some_and(|crit| crit.iter().any(|ext| ext.as_str() == Some("b64")));
+    if !listed_crit {
+        return Err(
+            "The header has a b64-field, but 'b64' is not listed in its 'crit'".to_string(),
+        );
+    }
+    Ok(b64)
 }
 
 fn assert_ascii_at(header: &serde_json::Value, path: &'static str) -> Result<(), String> {
@@ -74,7 +313,7 @@ fn assert_ascii_at(header: &serde_json::Value, path: &'static str) -> Result<(),
         format!("The provided header's {path}-field did not contain valid ASCII: '{content}'")
     })?;
     // Specified it has to be ascii
-    if !maybe_ascii.chars().all(|ch| ch.is_ascii()) {
+    if !maybe_ascii.is_ascii() {
         return Err(format!(
             "The provided header's {path}-field did not contain valid ASCII: '{maybe_ascii}'"
         ));
@@ -86,33 +325,270 @@ fn assert_ascii_at(header: &serde_json::Value, path: &'static str) -> Result<(),
 /// [Section for validation](https://datatracker.ietf.org/doc/html/rfc7515#section-3.3)
 fn validate_jws(
     header: serde_json::Value,
+    raw_header: &str,
     body: &str,
     signature: &str,
+    payload_b64: bool,
 ) -> Result<ValidatedOutput, String> {
     // The header is already validated
-    // Aside from validating the signature, which is out of scope, the body and signature just
-    // needs to be valid b64
-    let body_raw = try_decode_b64(body)?;
+    // The body and signature just needs to be valid b64, checking the signature is done
+    // separately, and only if a key is supplied
+    let body_raw = decode_payload(body, payload_b64)?;
     let sig_raw = try_decode_b64(signature)?;
-    if let Ok(utf8) = core::str::from_utf8(&body_raw) {
-        if let Ok(json) = serde_json::from_str(utf8) {
-            Ok(ValidatedOutput {
-                header,
-                payload: JwtPayload::Json(json),
-                _signature: sig_raw,
-            })
-        } else {
-            Ok(ValidatedOutput {
-                header,
-                payload: JwtPayload::Utf8(utf8.to_string()),
-                _signature: sig_raw,
+    let signing_input = format!("{raw_header}.{body}").into_bytes();
+    // The compact serialization can't tell a detached payload from an empty one
+    let (payload, inflated) = if body.is_empty() {
+        (JwtPayload::Detached, None)
+    } else {
+        let (body_raw, inflated) = inflate_payload(&header, body_raw)?;
+        (JwtPayload::from_bytes(body_raw), inflated)
+    };
+    Ok(ValidatedOutput {
+        header: header.clone(),
+        payload,
+        kind: TokenKind::Jws {
+            signatures: alloc::vec![JwsSignature {
+                header: header.clone(),
+                protected: header,
+                unprotected: None,
+                signature: sig_raw,
+                signing_input,
+            }],
+            serialization: JwsSerialization::Compact,
+        },
+        segments: alloc::vec![
+            ("header".to_string(), raw_header.to_string()),
+            ("payload".to_string(), body.to_string()),
+            ("signature".to_string(), signature.to_string()),
+        ],
+        inflated,
+    })
+}
+
+/// [RFC-7515](https://datatracker.ietf.org/doc/html/rfc7515#section-7.2)
+/// Both the general, and the flattened syntax. The token's header is the first signature's
+fn validate_json_serialization(raw: &str) -> Result<ValidatedOutput, String> {
+    let json: serde_json::Value = serde_json::from_str(raw)
+        .map_err(|e| format!("Failed to deserialize JSON serialized token: {e}"))?;
+    if json.get("ciphertext").is_some() {
+        return Err("The JWE JSON serialization is not supported".to_string());
+    }
+    let body = match json.get("payload") {
+        None => None,
+        Some(serde_json::Value::String(body)) => Some(body.as_str()),
+        Some(other) => {
+            return Err(format!(
+                "JSON serialized JWS 'payload'-member was not a string: '{other}'"
+            ))
+        }
+    };
+    let detached = body.is_none();
+    let body = body.unwrap_or_default();
+    let signatures = match json.get("signatures") {
+        Some(serde_json::Value::Array(signatures)) if !signatures.is_empty() => signatures
+            .iter()
+            .enumerate()
+            .map(|(ind, signature)| {
+                validate_json_signature(signature, body, &format!("signatures[{ind}]."))
             })
+            .collect::<Result<Vec<_>, _>>()?,
+        Some(other) => {
+            return Err(format!(
+                "JSON serialized JWS 'signatures'-member was not a non-empty array: '{other}'"
+            ))
         }
+        None => alloc::vec![validate_json_signature(&json, body, "")?],
+    };
+    let mut segments = alloc::vec![("payload".to_string(), body.to_string())];
+    for (_, signature_segments) in &signatures {
+        segments.extend(signature_segments.iter().cloned());
+    }
+    // The payload is shared, so all signatures have to agree on its encoding
+    let mut payload_b64 = None;
+    for (jws_signature, _) in &signatures {
+        if jws_signature
+            .unprotected
+            .as_ref()
+            .is_some_and(|unprotected| unprotected.get("b64").is_some())
+        {
+            return Err("The b64-field has to be in the protected header".to_string());
+        }
+        let b64 = payload_is_b64(&jws_signature.protected)?;
+        if payload_b64.is_some_and(|other| other != b64) {
+            return Err(
+                "The signatures disagree on whether the payload is b64 encoded".to_string(),
+            );
+        }
+        payload_b64 = Some(b64);
+    }
+    let header = signatures[0].0.header.clone();
+    let (payload, inflated) = if detached {
+        (JwtPayload::Detached, None)
     } else {
-        Ok(ValidatedOutput {
+        let (body_raw, inflated) =
+            inflate_payload(&header, decode_payload(body, payload_b64.unwrap_or(true))?)?;
+        (JwtPayload::from_bytes(body_raw), inflated)
+    };
+    Ok(ValidatedOutput {
+        header,
+        payload,
+        kind: TokenKind::Jws {
+            signatures: signatures
+                .into_iter()
+                .map(|(jws_signature, _)| jws_signature)
+                .collect(),
+            serialization: JwsSerialization::Json,
+        },
+        segments,
+        inflated,
+    })
+}
+
+/// [RFC-7515](https://datatracker.ietf.org/doc/html/rfc7515#section-7.2.1)
+/// Returns the signature along with its segments, named with the supplied prefix
+fn validate_json_signature(
+    raw: &serde_json::Value,
+    body: &str,
+    prefix: &str,
+) -> Result<(JwsSignature, [(String, String); 2]), String> {
+    let raw_protected = match raw.get("protected") {
+        None => "",
+        Some(serde_json::Value::String(protected)) => protected.as_str(),
+        Some(other) => {
+            return Err(format!(
+                "JSON serialized JWS 'protected'-member was not a string: '{other}'"
+            ))
+        }
+    };
+    let protected = if raw_protected.is_empty() {
+        serde_json::Value::Object(serde_json::Map::new())
+    } else {
+        let decoded = try_decode_string_value_b64(raw_protected)?;
+        serde_json::from_str(&decoded).map_err(|_e| {
+            format!("Failed to deserialize protected header, not a valid JSON object: {decoded}")
+        })?
+    };
+    let protected_members = protected
+        .as_object()
+        .ok_or_else(|| format!("Protected header was not a valid object: '{protected}'"))?;
+    critical_extensions(&protected)?;
+    let unprotected = raw.get("header");
+    if unprotected.is_some_and(|unprotected| unprotected.get("crit").is_some()) {
+        return Err("The crit-field has to be in the protected header".to_string());
+    }
+    let mut header = protected_members.clone();
+    if let Some(unprotected) = unprotected {
+        let unprotected_members = unprotected
+            .as_object()
+            .ok_or_else(|| format!("Unprotected header was not a valid object: '{unprotected}'"))?;
+        for (name, value) in unprotected_members {
+            // The header parameter names have to be disjoint
+            if header.insert(name.clone(), value.clone()).is_some() {
+                return Err(format!(
+                    "Header parameter '{name}' is in both the protected and unprotected header"
+                ));
+            }
+        }
+    }
+    let header = serde_json::Value::Object(header);
+    assert_ascii_at(&header, "alg")?;
+    let signature = raw
+        .get("signature")
+        .and_then(serde_json::Value::as_str)
+        .ok_or_else(|| "JSON serialized JWS did not contain a 'signature'-string".to_string())?;
+    Ok((
+        JwsSignature {
             header,
-            payload: JwtPayload::Raw(body_raw),
-            _signature: sig_raw,
-        })
+            protected,
+            unprotected: unprotected.cloned(),
+            signature: try_decode_b64(signature)?,
+            signing_input: format!("{raw_protected}.{body}").into_bytes(),
+        },
+        [
+            (format!("{prefix}protected"), raw_protected.to_string()),
+            (format!("{prefix}signature"), signature.to_string()),
+        ],
+    ))
+}
+
+/// [RFC-7516](https://datatracker.ietf.org/doc/html/rfc7516#section-4.1.3)
+/// Inflates a payload compressed with raw DEFLATE, if the header says `zip: DEF`.
+/// `zip` is only registered for JWE, but some issuers compress JWS payloads as well
+pub(crate) fn inflate_payload(
+    header: &serde_json::Value,
+    raw: Vec<u8>,
+) -> Result<(Vec<u8>, Option<Inflated>), String> {
+    let Some(zip) = header.get("zip") else {
+        return Ok((raw, None));
+    };
+    if zip.as_str() != Some("DEF") {
+        return Err(format!(
+            "Unsupported compression algorithm in the header's zip-field: '{zip}', only 'DEF' is supported"
+        ));
     }
+    let inflated = miniz_oxide::inflate::decompress_to_vec_with_limit(&raw, MAX_INFLATED_PAYLOAD)
+        .map_err(|e| {
+            if e.status == miniz_oxide::inflate::TINFLStatus::HasMoreOutput {
+                format!(
+                    "The zip DEF payload inflates to more than the max size of {MAX_INFLATED_PAYLOAD} bytes"
+                )
+            } else {
+                format!("Failed to inflate zip DEF payload: {e}")
+            }
+        })?;
+    let info = Inflated {
+        compressed_len: raw.len(),
+        inflated_len: inflated.len(),
+    };
+    Ok((inflated, Some(info)))
+}
+
+/// [RFC-7797](https://datatracker.ietf.org/doc/html/rfc7797#section-5)
+/// The signing input is the payload as it appears in the input, encoded or not
+fn decode_payload(body: &str, payload_b64: bool) -> Result<Vec<u8>, String> {
+    if payload_b64 {
+        try_decode_b64(body)
+    } else {
+        Ok(body.as_bytes().to_vec())
+    }
+}
+
+/// [RFC-7516](https://datatracker.ietf.org/doc/html/rfc7516#section-5.2)
+/// Decryption is done separately, and only if a key is supplied, the parts just need to be
+/// valid b64
+fn validate_jwe(
+    header: serde_json::Value,
+    raw_header: &str,
+    rest: &str,
+) -> Result<ValidatedOutput, String> {
+    let mut parts = rest.split('.');
+    let (Some(encrypted_key), Some(iv), Some(ciphertext), Some(tag), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return Err(format!("Interpreted input as a JWE, but the input did not contain 4 dots, rest of input='{rest}'"));
+    };
+    Ok(ValidatedOutput {
+        header,
+        payload: JwtPayload::Encrypted,
+        kind: TokenKind::Jwe(JweParts {
+            protected_header: raw_header.to_string(),
+            encrypted_key: try_decode_b64(encrypted_key)?,
+            iv: try_decode_b64(iv)?,
+            ciphertext: try_decode_b64(ciphertext)?,
+            tag: try_decode_b64(tag)?,
+        }),
+        segments: alloc::vec![
+            ("header".to_string(), raw_header.to_string()),
+            ("encrypted_key".to_string(), encrypted_key.to_string()),
+            ("iv".to_string(), iv.to_string()),
+            ("ciphertext".to_string(), ciphertext.to_string()),
+            ("tag".to_string(), tag.to_string()),
+        ],
+        // Only known once decrypted
+        inflated: None,
+    })
 }
@@ -1,26 +1,53 @@
 #![no_std]
 extern crate alloc;
 
+use crate::run::RunError;
 use alloc::string::String;
+use alloc::vec::Vec;
 use tiny_cli::ArgParse;
 use tiny_std::unix::cli::parse_cli_args;
 use tiny_std::{eprintln, UnixStr};
 
+mod cbor;
+mod claims;
+mod clock;
+mod cwt;
+mod jwe;
+mod jwk;
 mod jwt;
 mod read_line;
 pub mod run;
+mod scan;
+mod tz;
+mod verify;
 
 #[derive(Debug, ArgParse)]
 pub struct Args {
-    /// Pass the token as an argument, mandatory if not using `-p, --path`, should be
-    /// a single line, mutually exclusive with `-p, --path`.
+    /// Pass the token as an argument, if not using `-p, --path`, `--env`, or `--prompt`, or piping
+    /// it through stdin. Should be a single line, mutually exclusive with the others.
+    /// An `Authorization:` or `Bearer` prefix, surrounding quotes, and whitespace are stripped.
     pub stdin: Option<String>,
 
     /// Supply the token through a file, mandatory if not passing token using `s, --stdin`.
-    /// The first line of the file should contain the token, mutually exclusive with STDIN.
+    /// The file should contain the token, compact or JSON serialized, mutually exclusive with STDIN.
     #[cli(short = "p", long = "path")]
     pub path: Option<&'static UnixStr>,
 
+    /// Read the token from the named environment variable, unlike an argument it doesn't
+    /// show up in the process listing or the shell's history.
+    #[cli(long = "env")]
+    pub env: Option<&'static UnixStr>,
+
+    /// Prompt for the token on the controlling terminal, with echo turned off,
+    /// even if stdin is redirected.
+    #[cli(long = "prompt")]
+    pub prompt: bool,
+
+    /// The max number of bytes read from stdin or `--path`, or of a line with `--batch`,
+    /// defaults to 1 MiB.
+    #[cli(long = "max-input-size")]
+    pub max_input_size: Option<u64>,
+
     /// Print debug output, like the raw header component etc.
     #[cli(short = "v", long = "verbose")]
     pub verbose: bool,
@@ -35,15 +62,147 @@ pub struct Args {
     /// Accepted values:
     /// 1. `payload` for outputting the jwt claims as json
     /// 2. `header` for outputting the jwt header as json
+    /// 3. `json` for outputting everything, including verification and validation results,
+    ///    as a single json object
     #[cli(short = "o", long = "output")]
     pub output: Option<&'static UnixStr>,
+
+    /// How a payload that isn't utf8 is shown, instead of `[RAW BYTES]`
+    /// Accepted values:
+    /// 1. `hex` for the bytes as a hex string
+    /// 2. `hexdump` for an `xxd` style dump, with offsets and the printable characters
+    /// 3. `base64` for the bytes as standard, padded, base64
+    /// 4. `binary` for writing the bytes as-is to stdout, only with `-o payload`. There's no
+    ///    output path, redirect stdout to save them
+    #[cli(long = "raw-format")]
+    pub raw_format: Option<&'static UnixStr>,
+
+    /// Verify the token's signature, exits with code 2 if the signature does not match.
+    /// Supported algorithms: `HS256`, `HS384`, `HS512`, `RS256`, `RS384`, `RS512`,
+    /// `PS256`, `PS384`, `PS512`, `ES256`, `ES384`, `ES512`, and `EdDSA` (Ed25519 and Ed448).
+    /// Requires exactly one key, supplied through `--secret-path`, `--secret-env`, `--key-path`,
+    /// or `--jwks`.
+    #[cli(long = "verify")]
+    pub verify: bool,
+
+    /// Read the HMAC shared secret from a file, the file's content is used as-is,
+    /// including any trailing newline.
+    #[cli(long = "secret-path")]
+    pub secret_path: Option<&'static UnixStr>,
+
+    /// Read the HMAC shared secret from the named environment variable.
+    #[cli(long = "secret-env")]
+    pub secret_env: Option<&'static UnixStr>,
+
+    /// Read a public key from a file, PEM or DER encoded.
+    /// Accepts a `SubjectPublicKeyInfo` (`BEGIN PUBLIC KEY`) for RSA, P-256, P-384, P-521,
+    /// Ed25519, and Ed448 keys, or a PKCS#1 `BEGIN RSA PUBLIC KEY`.
+    #[cli(long = "key-path")]
+    pub key_path: Option<&'static UnixStr>,
+
+    /// Read a JSON Web Key Set from a file, the key is selected by the token header's `kid`,
+    /// and the key's `alg` and `use` if present.
+    /// Supports `RSA`, `EC`, `OKP`, and `oct` keys.
+    #[cli(long = "jwks")]
+    pub jwks: Option<&'static UnixStr>,
+
+    /// Decrypt a JWE with the key in this file, without a key only the header is shown.
+    /// Accepts a JWK (`oct`, `RSA`, or `EC`), or a PEM or DER encoded RSA or EC private key.
+    /// Supported algorithms: `dir`, `A128KW`, `A256KW`, `RSA-OAEP`, `RSA-OAEP-256`, `ECDH-ES`,
+    /// `ECDH-ES+A128KW`, `ECDH-ES+A256KW`, and encryptions: `A128GCM`, `A256GCM`,
+    /// `A128CBC-HS256`, `A256CBC-HS512`.
+    #[cli(long = "decrypt-key")]
+    pub decrypt_key: Option<&'static UnixStr>,
+
+    /// Decode the token as a CBOR Web Token, a `COSE_Sign1` or `COSE_Mac0`, as hex, base64,
+    /// or raw bytes. The signature or MAC is not verified.
+    #[cli(long = "cwt")]
+    pub cwt: bool,
+
+    /// Decode every line of `--path`, or of stdin, as a token of its own, skipping blank lines
+    /// and lines starting with `#`. Prints one JSON object per token, and continues past
+    /// tokens that fail, the exit code is that of the failures if they're of the same kind.
+    #[cli(long = "batch")]
+    pub batch: bool,
+
+    /// Find every compact JWS or JWE in the input, like a log, an HTTP header, a cookie, or a URL,
+    /// and decode each. Reads all of stdin rather than a line. Prints one JSON object per token,
+    /// like `--batch`, along with the line and byte offset where it was found.
+    #[cli(long = "scan")]
+    pub scan: bool,
+
+    /// Read the payload of a detached content JWS (`header..signature`) from a file,
+    /// the file's content is used as-is, and encoded unless the header has `b64: false`.
+    #[cli(long = "detached-payload")]
+    pub detached_payload: Option<&'static UnixStr>,
+
+    /// Validate the payload's time claims, `exp` has to be in the future, `nbf` in the past,
+    /// and `iat` can't be in the future. Exits with code 3 if any check fails.
+    #[cli(long = "validate")]
+    pub validate: bool,
+
+    /// Allowed clock skew in seconds when validating time claims, defaults to 0.
+    /// Only with `--validate`.
+    #[cli(long = "leeway")]
+    pub leeway: Option<u64>,
+
+    /// Expect the payload's `iss` to be this, exits with code 3 on a mismatch.
+    #[cli(long = "expect-iss")]
+    pub expect_iss: Option<String>,
+
+    /// Expect the payload's `aud` to be, or to contain, this, exits with code 3 on a mismatch.
+    #[cli(long = "expect-aud")]
+    pub expect_aud: Option<String>,
+
+    /// Expect the payload's `sub` to be this, exits with code 3 on a mismatch.
+    #[cli(long = "expect-sub")]
+    pub expect_sub: Option<String>,
+
+    /// Expect the payload's `azp` to be this, exits with code 3 on a mismatch.
+    #[cli(long = "expect-azp")]
+    pub expect_azp: Option<String>,
+
+    /// Expect a claim to have a value, as `key=value`, can be repeated.
+    /// The key is a claim name, or a JSON pointer like `/realm_access/roles/0`.
+    /// The value is compared as JSON if it parses as JSON, otherwise as a string.
+    /// Exits with code 3 on a mismatch.
+    #[cli(long = "claim")]
+    pub claim: Vec<String>,
+
+    /// Pin the current time used for readable times and claim validation, as unix seconds
+    /// or an RFC 3339 timestamp like `2024-08-30T12:00:00Z`.
+    #[cli(long = "at")]
+    pub at: Option<String>,
+
+    /// Time zone for readable times, defaults to `UTC`.
+    /// Accepts a fixed offset like `+02:00`, `local` for the zone in `/etc/localtime`,
+    /// or an absolute path to a TZif file like `/usr/share/zoneinfo/Europe/Stockholm`.
+    #[cli(long = "tz")]
+    pub tz: Option<String>,
+
+    /// Show a claim as a readable time, in addition to `exp`, `nbf`, `iat`, `auth_time`,
+    /// `updated_at`, and `toe`, can be repeated.
+    /// A claim name, or a JSON pointer where `*` matches every array element or object member,
+    /// like `/sessions/*/started_at`.
+    #[cli(long = "time-claim")]
+    pub time_claim: Vec<String>,
 }
 
 pub fn run() -> i32 {
     let args = parse_cli_args::<Args>();
-    if let Err(e) = run::run(args) {
-        eprintln!("Failed to run command: {e}");
-        return 1;
+    match run::run(args) {
+        Ok(()) => 0,
+        Err(RunError::Failed(e)) => {
+            eprintln!("Failed to run command: {e}");
+            1
+        }
+        Err(RunError::InvalidSignature(e)) => {
+            eprintln!("Signature verification failed: {e}");
+            2
+        }
+        Err(RunError::InvalidClaims(e)) => {
+            eprintln!("Claims validation failed: {e}");
+            3
+        }
     }
-    0
 }
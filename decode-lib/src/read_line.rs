@@ -1,30 +1,299 @@
 use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
-use rusl::platform::STDIN;
+use rusl::error::Errno;
+use rusl::platform::{Fd, SetAction, ECHO, ECHONL};
+use rusl::termios::{tcgetattr, tcsetattr};
+use tiny_std::unix::fd::AsRawFd;
+use tiny_std::UnixStr;
 
-pub(crate) fn stdin_readline() -> Result<String, String> {
-    let mut s = Vec::new();
+/// Input larger than this is rejected unless `--max-input-size` says otherwise,
+/// tokens are rarely more than a few kilobytes
+pub(crate) const DEFAULT_MAX_INPUT_SIZE: usize = 1024 * 1024;
+
+/// The terminal the process was started from, even if stdin is redirected
+const CONTROLLING_TTY: &UnixStr = UnixStr::from_str_checked("/dev/tty\0");
+
+/// Where input is read from, a file descriptor outside of tests
+pub(crate) trait Source {
+    /// Like `read(2)`, returns 0 at EOF
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, String>;
+}
+
+pub(crate) struct FdSource(pub(crate) Fd);
+
+impl Source for FdSource {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, String> {
+        loop {
+            match rusl::unistd::read(self.0, buf) {
+                Ok(bytes_read) => return Ok(bytes_read),
+                // Interrupted by a signal before anything was read
+                Err(e) if e.code == Some(Errno::EINTR) => {}
+                Err(e) => return Err(format!("Failed to read input into buf: {e}")),
+            }
+        }
+    }
+}
+
+/// Reads up until a newline, or EOF, the line ending isn't included.
+/// Anything after the newline is discarded
+pub(crate) fn read_line(source: &mut impl Source, max_size: usize) -> Result<String, String> {
+    let mut line = Vec::new();
     let mut buf = [0u8; 256];
-    let mut has_newline = false;
     loop {
-        let bytes_read = rusl::unistd::read(STDIN, &mut buf)
-            .map_err(|e| format!("Failed to read from stdin into buf: {e}"))?;
+        let bytes_read = source.read(&mut buf)?;
         if bytes_read == 0 {
-            return Err("Unexpected EOF waiting for stdin".to_string());
-        }
-        for (offset, byte) in buf[..bytes_read].iter().enumerate() {
-            if *byte == b'\n' {
-                s.extend_from_slice(&buf[..offset]);
-                has_newline = true;
-                break;
+            if line.is_empty() {
+                return Err("Unexpected EOF waiting for stdin, no token was supplied".to_string());
             }
+            break;
         }
-        if has_newline {
+        let read = &buf[..bytes_read];
+        let newline = read.iter().position(|byte| *byte == b'\n');
+        line.extend_from_slice(&read[..newline.unwrap_or(read.len())]);
+        check_size(line.len(), max_size)?;
+        if newline.is_some() {
             break;
         }
-        s.extend_from_slice(&buf);
     }
-    String::from_utf8(s)
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
         .map_err(|_| "Read up until newline from stdin, the bytes read was not utf-8".to_string())
 }
+
+/// Calls `on_line` with every line read, numbered from 1, without its line ending.
+/// A last line that doesn't end with a newline is included
+pub(crate) fn for_each_line(
+    source: &mut impl Source,
+    max_size: usize,
+    mut on_line: impl FnMut(usize, &[u8]),
+) -> Result<(), String> {
+    let mut line = Vec::new();
+    let mut line_number = 0;
+    let mut buf = [0u8; 4096];
+    loop {
+        let bytes_read = source.read(&mut buf)?;
+        if bytes_read == 0 {
+            break;
+        }
+        let mut rest = &buf[..bytes_read];
+        while let Some(newline) = rest.iter().position(|byte| *byte == b'\n') {
+            line.extend_from_slice(&rest[..newline]);
+            check_size(line.len(), max_size)
+                .map_err(|e| format!("Line {}: {e}", line_number + 1))?;
+            line_number += 1;
+            on_line(line_number, line.strip_suffix(b"\r").unwrap_or(&line));
+            line.clear();
+            rest = &rest[newline + 1..];
+        }
+        line.extend_from_slice(rest);
+        check_size(line.len(), max_size).map_err(|e| format!("Line {}: {e}", line_number + 1))?;
+    }
+    if !line.is_empty() {
+        on_line(line_number + 1, line.strip_suffix(b"\r").unwrap_or(&line));
+    }
+    Ok(())
+}
+
+/// Reads until EOF, for when the input is more than a line
+pub(crate) fn read_to_end(source: &mut impl Source, max_size: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let bytes_read = source.read(&mut buf)?;
+        if bytes_read == 0 {
+            return Ok(out);
+        }
+        out.extend_from_slice(&buf[..bytes_read]);
+        check_size(out.len(), max_size)?;
+    }
+}
+
+/// Prompts for a line on the controlling terminal with echo turned off, like a password prompt,
+/// so that the token isn't shown on screen, kept in the shell's history, or in the process'
+/// arguments. In canonical mode the terminal takes at most 4095 bytes per line
+pub(crate) fn prompt_tty(prompt: &str, max_size: usize) -> Result<String, String> {
+    let tty = tiny_std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(CONTROLLING_TTY)
+        .map_err(|e| format!("Failed to open the controlling terminal to prompt on: {e}"))?;
+    let fd = tty.as_raw_fd();
+    let original = tcgetattr(fd)
+        .map_err(|e| format!("Failed to get the controlling terminal's attributes: {e}"))?;
+    let mut no_echo = original;
+    // The newline is still echoed, so that the next output starts on a line of its own
+    no_echo.0.c_lflag &= !(ECHO as u32);
+    no_echo.0.c_lflag |= ECHONL as u32;
+    rusl::unistd::write(fd, prompt.as_bytes())
+        .map_err(|e| format!("Failed to write prompt to the controlling terminal: {e}"))?;
+    tcsetattr(fd, SetAction::FLUSH, &no_echo)
+        .map_err(|e| format!("Failed to turn off echo on the controlling terminal: {e}"))?;
+    let line = read_line(&mut FdSource(fd), max_size);
+    // Restored before looking at what was read, a failed read can't leave echo off
+    tcsetattr(fd, SetAction::NOW, &original)
+        .map_err(|e| format!("Failed to restore the controlling terminal's attributes: {e}"))?;
+    line
+}
+
+fn check_size(size: usize, max_size: usize) -> Result<(), String> {
+    if size > max_size {
+        return Err(format!(
+            "The input is larger than the max size of {max_size} bytes, raise it with `--max-input-size`"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Hands out the chunks one read at a time, like a pipe that's written to in pieces
+    struct MockFd {
+        chunks: Vec<Vec<u8>>,
+    }
+
+    impl MockFd {
+        fn new(chunks: &[&[u8]]) -> Self {
+            Self {
+                chunks: chunks.iter().rev().map(|chunk| chunk.to_vec()).collect(),
+            }
+        }
+    }
+
+    impl Source for MockFd {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, String> {
+            let Some(mut chunk) = self.chunks.pop() else {
+                return Ok(0);
+            };
+            if chunk.len() > buf.len() {
+                self.chunks.push(chunk.split_off(buf.len()));
+            }
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    struct FailingFd;
+
+    impl Source for FailingFd {
+        fn read(&mut self, _buf: &mut [u8]) -> Result<usize, String> {
+            Err("EBADF".to_string())
+        }
+    }
+
+    #[test]
+    fn reads_line_ending_with_newline() {
+        let mut fd = MockFd::new(&[b"eyJ.eyJ.sig\nignored"]);
+        assert_eq!("eyJ.eyJ.sig", read_line(&mut fd, 1024).unwrap());
+    }
+
+    #[test]
+    fn reads_line_ending_at_eof() {
+        let mut fd = MockFd::new(&[b"eyJ.eyJ.sig"]);
+        assert_eq!("eyJ.eyJ.sig", read_line(&mut fd, 1024).unwrap());
+    }
+
+    #[test]
+    fn strips_crlf() {
+        let mut fd = MockFd::new(&[b"eyJ.eyJ.sig\r\n"]);
+        assert_eq!("eyJ.eyJ.sig", read_line(&mut fd, 1024).unwrap());
+    }
+
+    #[test]
+    fn strips_crlf_split_across_reads() {
+        let mut fd = MockFd::new(&[b"eyJ.eyJ.sig\r", b"\n"]);
+        assert_eq!("eyJ.eyJ.sig", read_line(&mut fd, 1024).unwrap());
+    }
+
+    #[test]
+    fn joins_partial_reads() {
+        let mut fd = MockFd::new(&[b"eyJ", b".eyJ", b".s", b"ig\n"]);
+        assert_eq!("eyJ.eyJ.sig", read_line(&mut fd, 1024).unwrap());
+    }
+
+    #[test]
+    fn joins_partial_reads_longer_than_the_buffer() {
+        // A short read of a long line used to append the whole buffer, garbage included
+        let token = "a".repeat(300) + "." + &"b".repeat(700);
+        let (first, rest) = token.as_bytes().split_at(100);
+        let mut fd = MockFd::new(&[first, rest, b"\n"]);
+        assert_eq!(token, read_line(&mut fd, 4096).unwrap());
+    }
+
+    #[test]
+    fn empty_input_is_an_error() {
+        let mut fd = MockFd::new(&[]);
+        assert!(read_line(&mut fd, 1024).is_err());
+    }
+
+    #[test]
+    fn empty_line_is_empty() {
+        let mut fd = MockFd::new(&[b"\n"]);
+        assert_eq!("", read_line(&mut fd, 1024).unwrap());
+    }
+
+    #[test]
+    fn line_over_max_size_is_an_error() {
+        let mut fd = MockFd::new(&[b"0123456789", b"0\n"]);
+        assert!(read_line(&mut fd, 10).is_err());
+        let mut fd = MockFd::new(&[b"0123456789\n"]);
+        assert_eq!("0123456789", read_line(&mut fd, 10).unwrap());
+    }
+
+    #[test]
+    fn line_that_is_not_utf8_is_an_error() {
+        let mut fd = MockFd::new(&[&[0xff, 0xfe, b'\n']]);
+        assert!(read_line(&mut fd, 1024).is_err());
+    }
+
+    #[test]
+    fn read_error_is_an_error() {
+        assert!(read_line(&mut FailingFd, 1024).is_err());
+        assert!(read_to_end(&mut FailingFd, 1024).is_err());
+        assert!(for_each_line(&mut FailingFd, 1024, |_, _| {}).is_err());
+    }
+
+    #[test]
+    fn every_line_is_numbered() {
+        let mut fd = MockFd::new(&[b"first\r\nsec", b"ond\n\nlast"]);
+        let mut lines = Vec::new();
+        for_each_line(&mut fd, 1024, |number, line| {
+            lines.push((number, line.to_vec()));
+        })
+        .unwrap();
+        assert_eq!(
+            vec![
+                (1, b"first".to_vec()),
+                (2, b"second".to_vec()),
+                (3, Vec::new()),
+                (4, b"last".to_vec()),
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn every_line_over_max_size_is_an_error() {
+        let mut fd = MockFd::new(&[b"short\n0123456789", b"0123456789\n"]);
+        let mut lines = 0;
+        assert!(for_each_line(&mut fd, 16, |_, _| lines += 1).is_err());
+        assert_eq!(1, lines);
+    }
+
+    #[test]
+    fn reads_to_end() {
+        let mut fd = MockFd::new(&[b"a\n", b"b\r\n", b"c"]);
+        assert_eq!(b"a\nb\r\nc".to_vec(), read_to_end(&mut fd, 1024).unwrap());
+    }
+
+    #[test]
+    fn read_to_end_over_max_size_is_an_error() {
+        let mut fd = MockFd::new(&[b"0123456789", b"0"]);
+        assert!(read_to_end(&mut fd, 10).is_err());
+    }
+}
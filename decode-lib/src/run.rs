@@ -1,125 +1,1216 @@
-use crate::jwt::{jwt_is_valid, JwtPayload};
-use crate::read_line::stdin_readline;
+use crate::claims::{
+    for_each_pointer_match, pointer_tokens, validate_time_claims, ClaimExpectations,
+    DEFAULT_TIME_CLAIMS,
+};
+use crate::clock::Clock;
+use crate::cwt::{decode_input, validate_cwt, CoseStructure};
+use crate::jwe::{decrypt, DecryptionKey};
+use crate::jwt::{
+    critical_extensions, inflate_payload, jwt_is_valid, nested_token, JwsSerialization,
+    JwsSignature, JwtPayload, TokenKind, ValidatedOutput,
+};
+use crate::read_line::{
+    for_each_line, prompt_tty, read_line, read_to_end, FdSource, DEFAULT_MAX_INPUT_SIZE,
+};
+use crate::scan::scan_tokens;
+use crate::tz::TimeZone;
+use crate::verify::{verify_signature, KeySource, SignatureLength};
 use crate::Args;
 use alloc::format;
 use alloc::string::{String, ToString};
-use tiny_std::time::SystemTime;
+use alloc::vec::Vec;
+use base64::Engine;
+use rusl::platform::{STDIN, STDOUT};
+use tiny_std::unix::fd::AsRawFd;
 use tiny_std::{println, UnixStr};
 
-pub fn run(args: Args) -> Result<(), String> {
-    let input = match (args.stdin, args.path) {
-        (Some(_), Some(_)) => {
-            return Err("Supplied both stdin and a path to read from".to_string());
-        }
-        (Some(input), None) => input,
-        (None, Some(path)) => match tiny_std::fs::read_to_string(path) {
-            Ok(input) => input,
-            Err(e) => {
-                return Err(format!(
-                    "Failed to read jwt from supplied path: {path:?}, e: {e}"
-                ))
-            }
-        },
-        (None, None) => stdin_readline()?,
+#[derive(Debug)]
+pub enum RunError {
+    /// The token could not be processed
+    Failed(String),
+    /// The token was decoded, but its signature did not match the supplied key
+    InvalidSignature(String),
+    /// The token was decoded, but its claims did not pass validation
+    InvalidClaims(String),
+}
+
+impl From<String> for RunError {
+    fn from(value: String) -> Self {
+        Self::Failed(value)
+    }
+}
+
+/// Everything parsed from the arguments that applies to every token, in batch mode as well
+struct Options {
+    output: Option<Output>,
+    raw_format: Option<RawFormat>,
+    key: Option<KeySource>,
+    expectations: ClaimExpectations,
+    clock: Clock,
+    decryption_key: Option<DecryptionKey>,
+    detached: Option<Vec<u8>>,
+    readable_time: Option<ReadableTime>,
+    verbose: bool,
+    validate: bool,
+    leeway: Option<u64>,
+    cwt: bool,
+}
+
+impl Options {
+    fn parse(args: Args) -> Result<Self, RunError> {
+        let output = args.output.map(Output::parse).transpose()?;
+        let raw_format = args.raw_format.map(RawFormat::parse).transpose()?;
+        if matches!(raw_format, Some(RawFormat::Binary)) && !matches!(output, Some(Output::Payload))
+        {
+            return Err(RunError::Failed(
+                "`--raw-format binary` can only be used with `-o payload`".to_string(),
+            ));
+        }
+        if args.leeway.is_some() && !args.validate {
+            return Err(RunError::Failed(
+                "`--leeway` only applies to `--validate`, which wasn't supplied".to_string(),
+            ));
+        }
+        let key = if args.verify {
+            Some(KeySource::load(
+                args.secret_path,
+                args.secret_env,
+                args.key_path,
+                args.jwks,
+            )?)
+        } else {
+            None
+        };
+        let expectations = ClaimExpectations::parse(
+            args.expect_iss,
+            args.expect_aud,
+            args.expect_sub,
+            args.expect_azp,
+            args.claim,
+        )?;
+        let clock = Clock::parse(args.at.as_deref())?;
+        let decryption_key = args.decrypt_key.map(DecryptionKey::load).transpose()?;
+        let detached = args
+            .detached_payload
+            .map(|path| {
+                tiny_std::fs::read(path).map_err(|e| {
+                    format!("Failed to read detached payload from path: {path:?}, e: {e}")
+                })
+            })
+            .transpose()?;
+        if args.cwt && (decryption_key.is_some() || detached.is_some()) {
+            return Err(RunError::Failed(
+                "A CWT can't be decrypted, or have a detached payload attached".to_string(),
+            ));
+        }
+        let readable_time = if args.readable_time {
+            Some(ReadableTime {
+                now: clock.now(),
+                tz: TimeZone::parse(args.tz.as_deref())?,
+                claims: DEFAULT_TIME_CLAIMS
+                    .iter()
+                    .map(|claim| pointer_tokens(claim))
+                    .chain(args.time_claim.iter().map(|claim| pointer_tokens(claim)))
+                    .collect(),
+            })
+        } else {
+            None
+        };
+        Ok(Self {
+            output,
+            raw_format,
+            key,
+            expectations,
+            clock,
+            decryption_key,
+            detached,
+            readable_time,
+            verbose: args.verbose,
+            validate: args.validate,
+            leeway: args.leeway,
+            cwt: args.cwt,
+        })
+    }
+
+    /// Decodes the token, the layers of a nested one, the outermost first
+    fn decode(&self, input: Vec<u8>) -> Result<(Vec<ValidatedOutput>, Option<String>), String> {
+        if self.cwt {
+            return Ok((alloc::vec![validate_cwt(&decode_input(&input))?], None));
+        }
+        let input =
+            String::from_utf8(input).map_err(|_e| "The token was not valid utf8".to_string())?;
+        decode_layers(
+            input.as_str(),
+            self.decryption_key.as_ref(),
+            self.detached.as_deref(),
+        )
+    }
+
+    /// Many tokens are printed as one JSON object each, and share no detached payload
+    fn check_many(&self, flag: &str) -> Result<(), RunError> {
+        if !matches!(self.output, None | Some(Output::Json)) {
+            return Err(RunError::Failed(format!(
+                "`{flag}` prints one JSON object per token, it can't be combined with `-o header` or `-o payload`"
+            )));
+        }
+        if self.detached.is_some() {
+            return Err(RunError::Failed(format!(
+                "`{flag}` can't be combined with `--detached-payload`"
+            )));
+        }
+        Ok(())
+    }
+
+    /// The claims are only checked if asked to
+    fn check_claims(&self, layers: &[ValidatedOutput]) -> Result<Option<ClaimsCheck>, RunError> {
+        if !self.validate && self.expectations.is_empty() {
+            return Ok(None);
+        }
+        let leeway = self.validate.then(|| self.leeway.unwrap_or_default());
+        check_claims(
+            &layers[layers.len() - 1],
+            self.clock,
+            leeway,
+            &self.expectations,
+        )
+        .map(Some)
+    }
+}
+
+pub fn run(mut args: Args) -> Result<(), RunError> {
+    if args.batch && args.scan {
+        return Err(RunError::Failed(
+            "`--batch` and `--scan` are mutually exclusive".to_string(),
+        ));
+    }
+    let max_input_size = args.max_input_size.map_or(DEFAULT_MAX_INPUT_SIZE, |size| {
+        usize::try_from(size).unwrap_or(usize::MAX)
+    });
+    if args.batch {
+        if args.stdin.is_some() || args.env.is_some() || args.prompt {
+            return Err(RunError::Failed(
+                "`--batch` reads tokens from `--path` or piped stdin, not from an argument"
+                    .to_string(),
+            ));
+        }
+        let path = args.path;
+        return run_batch(&Options::parse(args)?, path, max_input_size);
+    }
+    if args.scan {
+        let input = read_input(
+            args.stdin.take(),
+            args.path,
+            args.env,
+            args.prompt,
+            true,
+            max_input_size,
+        )?;
+        return run_scan(&Options::parse(args)?, &input);
+    }
+    let input = read_input(
+        args.stdin.take(),
+        args.path,
+        args.env,
+        args.prompt,
+        // A binary CWT may contain newlines, and needn't be utf-8
+        args.cwt,
+        max_input_size,
+    )?;
+    let options = Options::parse(args)?;
+    // The envelope is the only thing printed to stdout when it's selected
+    let envelope = matches!(options.output, Some(Output::Json));
+    let verbose = options.verbose && !envelope;
+    let (input, stripped) = if options.cwt {
+        (input, Vec::new())
+    } else {
+        strip_wrappers(input)
+    };
+    if verbose && !stripped.is_empty() {
+        println!("Stripped {} from the input", stripped.join(", "));
+    }
+    let (layers, reconstructed) = options.decode(input)?;
+    if let (true, Some(reconstructed)) = (verbose, reconstructed) {
+        println!("Reconstructed token {reconstructed}");
+    }
+    print_layers(
+        &layers,
+        verbose,
+        options.readable_time.as_ref(),
+        options.output,
+        options.raw_format,
+    )?;
+    let signature_checks = if let Some(key) = &options.key {
+        let checks = check_signatures(outermost_signed(&layers), key)?;
+        if verbose {
+            print_signature_checks(&checks);
+        }
+        if !envelope {
+            signature_verdict(&checks)?;
+        }
+        Some(checks)
+    } else {
+        None
+    };
+    let claims_check = options.check_claims(&layers)?;
+    if let (true, Some(claims_check)) = (verbose, &claims_check) {
+        if claims_check.failures.is_empty() {
+            if claims_check.checked.is_empty() {
+                println!("Claims valid (no time claims)");
+            } else {
+                println!("Claims valid ({})", claims_check.checked.join(", "));
+            }
+        }
+    }
+    if envelope {
+        let pretty = serde_json::to_string_pretty(&json_envelope(
+            &layers,
+            signature_checks.as_deref(),
+            claims_check.as_ref(),
+        ))
+        .map_err(|e| format!("Failed to pretty print json envelope: {e}"))?;
+        println!("{pretty}");
+        if let Some(checks) = &signature_checks {
+            signature_verdict(checks)?;
+        }
+    }
+    claims_verdict(claims_check.as_ref())
+}
+
+/// A pasted token is often wrapped, in an `Authorization` header, in quotes, or broken over
+/// several lines by the terminal. Returns the token, and a description of what was stripped.
+/// JSON serialized tokens are left as they are, their whitespace may be part of a value
+fn strip_wrappers(input: Vec<u8>) -> (Vec<u8>, Vec<String>) {
+    let mut stripped = Vec::new();
+    let mut token = input.trim_ascii();
+    loop {
+        if let Some(rest) = strip_prefix_ignore_case(token, b"Authorization:") {
+            stripped.push("the 'Authorization:' prefix".to_string());
+            token = rest.trim_ascii();
+        } else if let Some(rest) = strip_prefix_ignore_case(token, b"Bearer")
+            .filter(|rest| rest.first().is_some_and(u8::is_ascii_whitespace))
+        {
+            stripped.push("the 'Bearer' prefix".to_string());
+            token = rest.trim_ascii();
+        } else if let Some(rest) = [b'"', b'\''].iter().find_map(|quote| {
+            token
+                .strip_prefix(&[*quote])
+                .and_then(|rest| rest.strip_suffix(&[*quote]))
+        }) {
+            stripped.push("surrounding quotes".to_string());
+            token = rest.trim_ascii();
+        } else {
+            break;
+        }
+    }
+    if token.starts_with(b"{") {
+        return (token.to_vec(), stripped);
+    }
+    let whitespace = token
+        .iter()
+        .filter(|byte| byte.is_ascii_whitespace())
+        .count();
+    match whitespace {
+        0 => {}
+        1 => stripped.push("1 whitespace character".to_string()),
+        _ => stripped.push(format!("{whitespace} whitespace characters")),
+    }
+    let token = token
+        .iter()
+        .copied()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    (token, stripped)
+}
+
+fn strip_prefix_ignore_case<'a>(input: &'a [u8], prefix: &[u8]) -> Option<&'a [u8]> {
+    let (head, rest) = input.split_at_checked(prefix.len())?;
+    head.eq_ignore_ascii_case(prefix).then_some(rest)
+}
+
+/// The outermost signature, for a signed-then-encrypted token that's the inner JWS
+fn outermost_signed(layers: &[ValidatedOutput]) -> &ValidatedOutput {
+    layers
+        .iter()
+        .find(|layer| matches!(layer.kind, TokenKind::Jws { .. }))
+        .unwrap_or(&layers[0])
+}
+
+fn claims_verdict(claims_check: Option<&ClaimsCheck>) -> Result<(), RunError> {
+    match claims_check {
+        Some(claims_check) if !claims_check.failures.is_empty() => {
+            Err(RunError::InvalidClaims(claims_check.failures.join("; ")))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Decodes every line as a token of its own, skipping blank lines and `#` comments.
+/// Continues past tokens that fail
+fn run_batch(
+    options: &Options,
+    path: Option<&'static UnixStr>,
+    max_input_size: usize,
+) -> Result<(), RunError> {
+    options.check_many("--batch")?;
+    let mut report = BatchReport::default();
+    let mut on_line = |line_number: usize, line: &[u8]| {
+        let line = line.trim_ascii();
+        if line.is_empty() || line.starts_with(b"#") {
+            return;
+        }
+        // Like a single token, a CWT isn't a pasted JWT that needs unwrapping
+        let line = if options.cwt {
+            line.to_vec()
+        } else {
+            strip_wrappers(line.to_vec()).0
+        };
+        report.decode(options, line, line_number, None);
+    };
+    if let Some(path) = path {
+        let file = tiny_std::fs::File::open(path)
+            .map_err(|e| format!("Failed to open batch file: {path:?}, e: {e}"))?;
+        for_each_line(
+            &mut FdSource(file.as_raw_fd()),
+            max_input_size,
+            &mut on_line,
+        )?;
+    } else {
+        for_each_line(&mut FdSource(STDIN), max_input_size, &mut on_line)?;
+    }
+    report.verdict()
+}
+
+/// Decodes every token found in the input, printed like `--batch` along with where in the
+/// input the token was found
+fn run_scan(options: &Options, input: &[u8]) -> Result<(), RunError> {
+    options.check_many("--scan")?;
+    if options.cwt {
+        return Err(RunError::Failed(
+            "`--scan` finds compact JWSs and JWEs, it can't be combined with `--cwt`".to_string(),
+        ));
+    }
+    let found = scan_tokens(input);
+    if found.is_empty() {
+        return Err(RunError::Failed("Found no tokens in the input".to_string()));
+    }
+    let mut report = BatchReport::default();
+    for found_token in found {
+        report.decode(
+            options,
+            found_token.token.into_bytes(),
+            found_token.line,
+            Some(found_token.offset),
+        );
+    }
+    report.verdict()
+}
+
+/// The outcome of decoding many tokens, each is printed as a JSON object on a line of its own
+#[derive(Default)]
+struct BatchReport {
+    total: usize,
+    /// Along with the line the token is on
+    failures: Vec<(usize, RunError)>,
+}
+
+impl BatchReport {
+    fn decode(&mut self, options: &Options, input: Vec<u8>, line: usize, offset: Option<usize>) {
+        self.total += 1;
+        let (mut result, outcome) = batch_token(options, input);
+        result.insert("line".to_string(), line.into());
+        if let Some(offset) = offset {
+            result.insert("offset".to_string(), offset.into());
+        }
+        let (status, error) = match &outcome {
+            Ok(()) => ("ok", None),
+            Err(RunError::Failed(e)) => ("failed", Some(e.clone())),
+            Err(RunError::InvalidSignature(e)) => ("invalid_signature", Some(e.clone())),
+            Err(RunError::InvalidClaims(e)) => ("invalid_claims", Some(e.clone())),
+        };
+        result.insert("status".to_string(), status.into());
+        if let Some(error) = error {
+            result.insert("error".to_string(), error.into());
+        }
+        println!("{}", serde_json::Value::Object(result));
+        if let Err(e) = outcome {
+            self.failures.push((line, e));
+        }
+    }
+
+    /// The exit code is that of the failures if they're all of the same kind
+    fn verdict(self) -> Result<(), RunError> {
+        let Some((first_line, first)) = self.failures.first() else {
+            return Ok(());
+        };
+        let summary = format!(
+            "{} of {} tokens failed, the first on line {first_line}",
+            self.failures.len(),
+            self.total
+        );
+        let same_kind = self
+            .failures
+            .iter()
+            .all(|(_, e)| core::mem::discriminant(e) == core::mem::discriminant(first));
+        Err(match first {
+            RunError::InvalidSignature(_) if same_kind => RunError::InvalidSignature(summary),
+            RunError::InvalidClaims(_) if same_kind => RunError::InvalidClaims(summary),
+            _ => RunError::Failed(summary),
+        })
+    }
+}
+
+/// The same checks as for a single token, the result is the envelope without the pretty
+/// printing, or just the error if the token couldn't be decoded
+fn batch_token(
+    options: &Options,
+    input: Vec<u8>,
+) -> (
+    serde_json::Map<String, serde_json::Value>,
+    Result<(), RunError>,
+) {
+    let checked = options
+        .decode(input)
+        .map_err(RunError::Failed)
+        .and_then(|(layers, _)| {
+            let signature_checks = options
+                .key
+                .as_ref()
+                .map(|key| check_signatures(outermost_signed(&layers), key))
+                .transpose()?;
+            let claims_check = options.check_claims(&layers)?;
+            Ok((layers, signature_checks, claims_check))
+        });
+    let (layers, signature_checks, claims_check) = match checked {
+        Ok(checked) => checked,
+        Err(e) => return (serde_json::Map::new(), Err(e)),
+    };
+    let envelope = json_envelope(&layers, signature_checks.as_deref(), claims_check.as_ref());
+    let outcome = signature_checks
+        .as_deref()
+        .map_or(Ok(()), signature_verdict)
+        .and_then(|()| claims_verdict(claims_check.as_ref()));
+    (envelope, outcome)
+}
+
+/// The token's bytes, a CWT may be binary. Piped stdin is read up until the first newline,
+/// unless `whole_stdin` is set, then it's read to the end as is.
+/// An argument shows up in the process listing, and the shell's history, `--env` and `--prompt`
+/// don't
+fn read_input(
+    stdin: Option<String>,
+    path: Option<&'static UnixStr>,
+    env: Option<&'static UnixStr>,
+    prompt: bool,
+    whole_stdin: bool,
+    max_input_size: usize,
+) -> Result<Vec<u8>, String> {
+    match (stdin, path, env, prompt) {
+        (Some(input), None, None, false) => Ok(input.into_bytes()),
+        (None, Some(path), None, false) => {
+            let file = tiny_std::fs::File::open(path)
+                .map_err(|e| format!("Failed to read jwt from supplied path: {path:?}, e: {e}"))?;
+            read_to_end(&mut FdSource(file.as_raw_fd()), max_input_size)
+                .map_err(|e| format!("Failed to read jwt from supplied path: {path:?}, e: {e}"))
+        }
+        (None, None, Some(var), false) => {
+            let value = tiny_std::env::var_unix(var)
+                .map_err(|_e| format!("Failed to read jwt from environment variable {var:?}"))?
+                .as_slice();
+            // Without the null terminator
+            Ok(value.strip_suffix(b"\0").unwrap_or(value).to_vec())
+        }
+        (None, None, None, true) => prompt_tty("Token: ", max_input_size).map(String::into_bytes),
+        (None, None, None, false) if whole_stdin => {
+            read_to_end(&mut FdSource(STDIN), max_input_size)
+        }
+        (None, None, None, false) => {
+            read_line(&mut FdSource(STDIN), max_input_size).map(String::into_bytes)
+        }
+        _ => Err(
+            "Supplied more than one of a token argument, `--path`, `--env`, and `--prompt` to read from"
+                .to_string(),
+        ),
+    }
+}
+
+/// The outcome of checking one of a JWS' signatures
+struct SignatureCheck {
+    alg: String,
+    kid: Option<String>,
+    /// Whether the signature matched, or why it couldn't be checked
+    outcome: Result<bool, String>,
+}
+
+/// Checks every signature that the supplied key can be selected for
+fn check_signatures(
+    validated: &ValidatedOutput,
+    key_source: &KeySource,
+) -> Result<Vec<SignatureCheck>, RunError> {
+    let signatures = match &validated.kind {
+        TokenKind::Jws { signatures, .. } => signatures,
+        TokenKind::Jwe(_) => {
+            return Err(RunError::Failed(
+                "The token is a JWE, there's no signature to verify".to_string(),
+            ))
+        }
+        TokenKind::Cose(_) => {
+            return Err(RunError::Failed(
+                "Verifying the signature or MAC of a CWT is not supported".to_string(),
+            ))
+        }
+    };
+    if matches!(validated.payload, JwtPayload::Detached) {
+        return Err(RunError::Failed(
+            "The token's payload is detached, supply it through `--detached-payload`".to_string(),
+        ));
+    }
+    Ok(signatures
+        .iter()
+        .map(|jws_signature| {
+            // Presence and ascii-ness is checked when validating the header
+            let alg = jws_signature
+                .header
+                .get("alg")
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or_default();
+            match key_source.select(&jws_signature.header, alg) {
+                Ok((key, kid)) => SignatureCheck {
+                    alg: alg.to_string(),
+                    kid: kid.map(ToString::to_string),
+                    outcome: verify_signature(
+                        alg,
+                        key,
+                        &jws_signature.signing_input,
+                        &jws_signature.signature,
+                    ),
+                },
+                Err(e) => SignatureCheck {
+                    alg: alg.to_string(),
+                    kid: None,
+                    outcome: Err(e),
+                },
+            }
+        })
+        .collect())
+}
+
+fn print_signature_checks(checks: &[SignatureCheck]) {
+    for (ind, check) in checks.iter().enumerate() {
+        let marker = if checks.len() == 1 {
+            String::new()
+        } else {
+            format!(" [{ind}]")
+        };
+        let alg = &check.alg;
+        match (&check.outcome, &check.kid) {
+            (Ok(valid), kid) => {
+                let verdict = if *valid { "valid" } else { "invalid" };
+                if let Some(kid) = kid {
+                    println!("Signature{marker} {verdict} ({alg}, kid: {kid})");
+                } else {
+                    println!("Signature{marker} {verdict} ({alg})");
+                }
+            }
+            (Err(e), _) => {
+                if checks.len() > 1 {
+                    println!("Signature{marker} skipped ({alg}): {e}");
+                }
+            }
+        }
+    }
+}
+
+/// A JSON serialized JWS may have several signatures, it's valid if any of them can be checked
+/// with the supplied key and matches
+fn signature_verdict(checks: &[SignatureCheck]) -> Result<(), RunError> {
+    if checks.iter().any(|check| check.outcome == Ok(true)) {
+        return Ok(());
+    }
+    if let Some(mismatched) = checks.iter().find(|check| check.outcome == Ok(false)) {
+        return Err(RunError::InvalidSignature(format!(
+            "The token's {} signature does not match the supplied key",
+            mismatched.alg
+        )));
+    }
+    match checks.iter().find_map(|check| check.outcome.clone().err()) {
+        Some(e) => Err(RunError::Failed(e)),
+        None => Err(RunError::Failed(
+            "The token has no signatures to verify".to_string(),
+        )),
+    }
+}
+
+/// The outcome of validating the claims, every failed check is reported
+struct ClaimsCheck {
+    checked: Vec<String>,
+    failures: Vec<String>,
+}
+
+/// The claims are the innermost token's payload, time claims are only validated if there's
+/// a leeway
+fn check_claims(
+    validated: &ValidatedOutput,
+    clock: Clock,
+    leeway: Option<u64>,
+    expectations: &ClaimExpectations,
+) -> Result<ClaimsCheck, RunError> {
+    let JwtPayload::Json(claims) = &validated.payload else {
+        return Err(RunError::Failed(
+            "The token's payload is not a JSON claims set, there's nothing to validate".to_string(),
+        ));
     };
-    let output = args.output.map(Output::parse).transpose()?;
-    decode_no_sig_check(input.as_str(), args.verbose, args.readable_time, output)
+    let mut checked = Vec::new();
+    let mut failures = Vec::new();
+    if let Some(leeway) = leeway {
+        match validate_time_claims(claims, clock.now(), leeway) {
+            Ok(time_claims) => checked.extend(time_claims.into_iter().map(ToString::to_string)),
+            Err(time_failures) => failures.extend(time_failures),
+        }
+    }
+    match expectations.check(claims) {
+        Ok(expected) => checked.extend(expected),
+        Err(mismatches) => failures.extend(mismatches),
+    }
+    Ok(ClaimsCheck { checked, failures })
 }
 
-fn decode_no_sig_check(
+/// Tokens nested deeper than this are rejected, the outermost token is at depth 0
+pub const MAX_NESTING_DEPTH: usize = 8;
+
+/// [RFC-7519](https://datatracker.ietf.org/doc/html/rfc7519#section-11.2)
+/// Decodes the token, and every token nested inside of it, the outermost first.
+/// A detached payload belongs to the outermost token, if it's compact serialized the
+/// reconstructed token is returned as well
+fn decode_layers(
     input: &str,
+    decryption_key: Option<&DecryptionKey>,
+    detached: Option<&[u8]>,
+) -> Result<(Vec<ValidatedOutput>, Option<String>), String> {
+    let mut layers: Vec<ValidatedOutput> = Vec::new();
+    let mut reconstructed = None;
+    let mut current = input.trim().to_string();
+    loop {
+        let mut validated = jwt_is_valid(&current)?;
+        if let (Some(detached), true) = (detached, layers.is_empty()) {
+            reconstructed = validated.attach_payload(detached)?;
+        }
+        if let (Some(key), TokenKind::Jwe(parts)) = (decryption_key, &validated.kind) {
+            let (plaintext, inflated) =
+                inflate_payload(&validated.header, decrypt(&validated.header, parts, key)?)?;
+            validated.payload = JwtPayload::from_bytes(plaintext);
+            validated.inflated = inflated;
+        }
+        let Some(inner) = nested_token(&validated)?.map(ToString::to_string) else {
+            layers.push(validated);
+            break;
+        };
+        layers.push(validated);
+        if layers.len() > MAX_NESTING_DEPTH {
+            return Err(format!(
+                "The token is nested deeper than the max depth of {MAX_NESTING_DEPTH}"
+            ));
+        }
+        current = inner;
+    }
+    if decryption_key.is_some()
+        && !layers
+            .iter()
+            .any(|layer| matches!(layer.kind, TokenKind::Jwe(_)))
+    {
+        return Err("Supplied a decryption key, but the token is a JWS".to_string());
+    }
+    Ok((layers, reconstructed))
+}
+
+/// When the token is nested, each layer is prefixed with its depth, `-o` selects from the
+/// innermost layer
+fn print_layers(
+    layers: &[ValidatedOutput],
     verbose: bool,
-    fmt_time: bool,
+    fmt_time: Option<&ReadableTime>,
     output: Option<Output>,
+    raw_format: Option<RawFormat>,
 ) -> Result<(), String> {
-    let validated = jwt_is_valid(input.trim())?;
+    let innermost = layers.len() - 1;
     if verbose {
-        let pretty = match serde_json::to_string_pretty(&validated.header) {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(format!("Failed to prettify json to print jwt header: {e}"));
+        for (depth, layer) in layers.iter().enumerate() {
+            let marker = if innermost == 0 {
+                String::new()
+            } else {
+                format!("[depth {depth}] ")
+            };
+            if let TokenKind::Cose(parts) = &layer.kind {
+                println!("{marker}Structure {}", parts.structure.name());
+                let pretty = fmt_header(&parts.protected)?;
+                println!("{marker}Protected header {pretty}");
+                let pretty = fmt_header(&parts.unprotected)?;
+                println!("{marker}Unprotected header {pretty}");
+            } else if let TokenKind::Jws {
+                signatures,
+                serialization: JwsSerialization::Json,
+            } = &layer.kind
+            {
+                for (ind, jws_signature) in signatures.iter().enumerate() {
+                    let pretty = fmt_header(&jws_signature.protected)?;
+                    println!("{marker}Protected header [{ind}] {pretty}");
+                    if let Some(unprotected) = &jws_signature.unprotected {
+                        let pretty = fmt_header(unprotected)?;
+                        println!("{marker}Unprotected header [{ind}] {pretty}");
+                    }
+                    print_critical_extensions(&jws_signature.protected, &marker)?;
+                }
+            } else {
+                let pretty = fmt_header(&layer.header)?;
+                println!("{marker}Header {pretty}");
+                print_critical_extensions(&layer.header, &marker)?;
             }
-        };
-        println!("Header {pretty}",);
-    }
-    if verbose {
-        let pretty = fmt_payload(&validated.payload, fmt_time)?;
-        println!("Payload {pretty}");
+            let pretty = if depth == innermost {
+                fmt_payload(&layer.payload, fmt_time, raw_format)?
+            } else {
+                "[NESTED JWT]".to_string()
+            };
+            println!("{marker}Payload {pretty}");
+            if let Some(inflated) = layer.inflated {
+                println!(
+                    "{marker}Payload inflated with zip DEF, {} bytes compressed, {} bytes inflated",
+                    inflated.compressed_len, inflated.inflated_len
+                );
+            }
+            print_segments(layer, &marker);
+        }
     }
 
     if let Some(selection) = output {
+        let validated = &layers[innermost];
         match selection {
             Output::Payload => {
-                let pretty = fmt_payload(&validated.payload, false)?;
-                println!("{pretty}");
+                if let (JwtPayload::Raw(raw), Some(RawFormat::Binary)) =
+                    (&validated.payload, raw_format)
+                {
+                    write_stdout(raw)?;
+                } else {
+                    let pretty = fmt_payload(&validated.payload, None, raw_format)?;
+                    println!("{pretty}");
+                }
             }
             Output::Header => {
-                let pretty = match serde_json::to_string_pretty(&validated.header) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        return Err(format!("Failed to prettify json to print jwt header: {e}"));
-                    }
+                let pretty = if let TokenKind::Jws {
+                    signatures,
+                    serialization: JwsSerialization::Json,
+                } = &validated.kind
+                {
+                    fmt_header(&json_serialized_headers(signatures))?
+                } else {
+                    fmt_header(&validated.header)?
                 };
                 println!("{pretty}");
             }
+            // Printed after the checks, since it includes their results
+            Output::Json => {}
         }
     }
     Ok(())
 }
 
-fn fmt_payload(jwt_payload: &JwtPayload, fmt_time: bool) -> Result<String, String> {
+/// Describes the innermost layer, along with the results of any signature verification and
+/// claims validation. When the token is nested every layer is described in `layers`,
+/// the outermost first
+fn json_envelope(
+    layers: &[ValidatedOutput],
+    signature_checks: Option<&[SignatureCheck]>,
+    claims_check: Option<&ClaimsCheck>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut envelope = json_layer(&layers[layers.len() - 1]);
+    if layers.len() > 1 {
+        envelope.insert(
+            "layers".to_string(),
+            layers
+                .iter()
+                .map(|layer| serde_json::Value::Object(json_layer(layer)))
+                .collect(),
+        );
+    }
+    let verification = signature_checks.map(|checks| {
+        let signatures = checks
+            .iter()
+            .map(|check| {
+                let (result, error) = match &check.outcome {
+                    Ok(true) => ("valid", None),
+                    Ok(false) => ("invalid", None),
+                    Err(e) => ("skipped", Some(e.clone())),
+                };
+                serde_json::json!({
+                    "alg": check.alg,
+                    "kid": check.kid,
+                    "result": result,
+                    "error": error,
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "valid": checks.iter().any(|check| check.outcome == Ok(true)),
+            "signatures": signatures,
+        })
+    });
+    envelope.insert("verification".to_string(), verification.unwrap_or_default());
+    let validation = claims_check.map(|claims_check| {
+        serde_json::json!({
+            "valid": claims_check.failures.is_empty(),
+            "checked": claims_check.checked,
+            "failures": claims_check.failures,
+        })
+    });
+    envelope.insert("validation".to_string(), validation.unwrap_or_default());
+    envelope
+}
+
+fn json_layer(layer: &ValidatedOutput) -> serde_json::Map<String, serde_json::Value> {
+    let (payload, payload_kind) = match &layer.payload {
+        JwtPayload::Json(json) => (json.clone(), "json"),
+        JwtPayload::Utf8(utf8) => (serde_json::Value::String(utf8.clone()), "utf8"),
+        JwtPayload::Raw(raw) => (
+            serde_json::Value::String(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(raw)),
+            "raw",
+        ),
+        JwtPayload::Encrypted => (serde_json::Value::Null, "encrypted"),
+        JwtPayload::Detached => (serde_json::Value::Null, "detached"),
+    };
+    let mut json = serde_json::Map::new();
+    json.insert("header".to_string(), layer.header.clone());
+    json.insert("payload".to_string(), payload);
+    json.insert("payload_kind".to_string(), payload_kind.into());
+    let signature = match &layer.kind {
+        TokenKind::Jws {
+            signatures,
+            serialization,
+        } => {
+            if *serialization == JwsSerialization::Json {
+                json.insert(
+                    "signatures".to_string(),
+                    signatures
+                        .iter()
+                        .map(|jws_signature| {
+                            let mut signature = json_signature(&jws_signature.signature);
+                            signature.extend(json_serialized_header(jws_signature));
+                            serde_json::Value::Object(signature)
+                        })
+                        .collect(),
+                );
+            }
+            serde_json::Value::Object(json_signature(&signatures[0].signature))
+        }
+        TokenKind::Jwe(_) => serde_json::Value::Null,
+        TokenKind::Cose(parts) => {
+            json.insert("structure".to_string(), parts.structure.name().into());
+            serde_json::Value::Object(json_signature(&parts.signature))
+        }
+    };
+    json.insert("signature".to_string(), signature);
+    json.insert(
+        "segments".to_string(),
+        layer
+            .segments
+            .iter()
+            .map(|(name, segment)| (name.clone(), serde_json::Value::String(segment.clone())))
+            .collect(),
+    );
+    json
+}
+
+fn json_signature(signature: &[u8]) -> serde_json::Map<String, serde_json::Value> {
+    let mut json = serde_json::Map::new();
+    json.insert(
+        "base64url".to_string(),
+        base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(signature)
+            .into(),
+    );
+    json.insert("hex".to_string(), fmt_hex(signature).into());
+    json
+}
+
+fn fmt_hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        out.push_str(&format!("{byte:02x}"));
+    }
+    out
+}
+
+/// The segments as they appeared in the input, and the decoded signatures, since a signature
+/// that's too short for its alg likely means that the token was truncated
+fn print_segments(layer: &ValidatedOutput, marker: &str) {
+    for (name, segment) in &layer.segments {
+        if segment.is_empty() {
+            println!("{marker}Raw {name} [EMPTY]");
+        } else {
+            println!("{marker}Raw {name} {segment}");
+        }
+    }
+    let (label, signatures) = match &layer.kind {
+        TokenKind::Jws { signatures, .. } => (
+            "Signature",
+            signatures
+                .iter()
+                .map(|jws_signature| (&jws_signature.header, jws_signature.signature.as_slice()))
+                .collect::<Vec<_>>(),
+        ),
+        TokenKind::Jwe(_) => return,
+        TokenKind::Cose(parts) => (
+            if parts.structure == CoseStructure::Mac0 {
+                "Tag"
+            } else {
+                "Signature"
+            },
+            alloc::vec![(&layer.header, parts.signature.as_slice())],
+        ),
+    };
+    for (ind, (header, signature)) in signatures.iter().enumerate() {
+        let signature_marker = if signatures.len() == 1 {
+            String::new()
+        } else {
+            format!(" [{ind}]")
+        };
+        let alg = header
+            .get("alg")
+            .and_then(serde_json::Value::as_str)
+            .unwrap_or_default();
+        let len = signature.len();
+        let expected = match SignatureLength::of_alg(alg) {
+            Some(expected) if expected.matches(len) => format!(", as expected for {alg}"),
+            Some(expected) => {
+                format!(", {alg} implies {expected}, the token may be truncated")
+            }
+            None => String::new(),
+        };
+        println!(
+            "{marker}{label}{signature_marker} hex {} ({len} bytes{expected})",
+            fmt_hex(signature)
+        );
+    }
+}
+
+fn print_critical_extensions(protected: &serde_json::Value, marker: &str) -> Result<(), String> {
+    let extensions = critical_extensions(protected)?;
+    if !extensions.is_empty() {
+        println!(
+            "{marker}Critical extensions understood: {}",
+            extensions.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// The headers in the shape of the general JWS JSON serialization
+fn json_serialized_headers(signatures: &[JwsSignature]) -> serde_json::Value {
+    signatures
+        .iter()
+        .map(|jws_signature| serde_json::Value::Object(json_serialized_header(jws_signature)))
+        .collect()
+}
+
+fn json_serialized_header(
+    jws_signature: &JwsSignature,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut headers = serde_json::Map::new();
+    headers.insert("protected".to_string(), jws_signature.protected.clone());
+    if let Some(unprotected) = &jws_signature.unprotected {
+        headers.insert("header".to_string(), unprotected.clone());
+    }
+    headers
+}
+
+fn fmt_header(header: &serde_json::Value) -> Result<String, String> {
+    serde_json::to_string_pretty(header)
+        .map_err(|e| format!("Failed to prettify json to print jwt header: {e}"))
+}
+
+fn fmt_payload(
+    jwt_payload: &JwtPayload,
+    fmt_time: Option<&ReadableTime>,
+    raw_format: Option<RawFormat>,
+) -> Result<String, String> {
     match jwt_payload {
         JwtPayload::Json(j) => {
-            if fmt_time {
-                format_time(j)
+            if let Some(readable) = fmt_time {
+                format_time(j, readable)
             } else {
                 serde_json::to_string_pretty(j)
                     .map_err(|_e| "Failed to pretty print json payload".to_string())
             }
         }
         JwtPayload::Utf8(s) => Ok(s.clone()),
-        JwtPayload::Raw(_) => Ok("[RAW BYTES]".to_string()),
+        JwtPayload::Raw(raw) => Ok(match raw_format {
+            Some(RawFormat::Hex) => fmt_hex(raw),
+            Some(RawFormat::Hexdump) => fmt_hexdump(raw),
+            Some(RawFormat::Base64) => base64::engine::general_purpose::STANDARD.encode(raw),
+            // Written as-is only when it's the sole output
+            Some(RawFormat::Binary) | None => "[RAW BYTES]".to_string(),
+        }),
+        JwtPayload::Encrypted => Ok("[ENCRYPTED]".to_string()),
+        JwtPayload::Detached => Ok("[DETACHED]".to_string()),
     }
 }
 
-fn format_time(json: &serde_json::Value) -> Result<String, String> {
+/// Like `xxd`, 16 bytes per line as hex in pairs, followed by the printable ascii characters
+fn fmt_hexdump(bytes: &[u8]) -> String {
+    let mut lines = Vec::new();
+    for (ind, chunk) in bytes.chunks(16).enumerate() {
+        let mut line = format!("{:08x}:", ind * 16);
+        for pos in 0..16 {
+            if pos % 2 == 0 {
+                line.push(' ');
+            }
+            match chunk.get(pos) {
+                Some(byte) => line.push_str(&format!("{byte:02x}")),
+                None => line.push_str("  "),
+            }
+        }
+        line.push_str("  ");
+        line.extend(chunk.iter().map(|byte| {
+            if byte.is_ascii_graphic() || *byte == b' ' {
+                char::from(*byte)
+            } else {
+                '.'
+            }
+        }));
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+fn write_stdout(mut bytes: &[u8]) -> Result<(), String> {
+    while !bytes.is_empty() {
+        let written = rusl::unistd::write(STDOUT, bytes)
+            .map_err(|e| format!("Failed to write raw payload to stdout: {e}"))?;
+        bytes = &bytes[written..];
+    }
+    Ok(())
+}
+
+/// How times in the payload are made readable
+struct ReadableTime {
+    /// Seconds since the unix epoch that relative times are computed from
+    now: i64,
+    tz: TimeZone,
+    /// JSON pointers to the claims that are NumericDates
+    claims: Vec<Vec<String>>,
+}
+
+fn format_time(json: &serde_json::Value, readable: &ReadableTime) -> Result<String, String> {
     let mut to_modify = json.clone();
-    fmt_if_present(&mut to_modify, "iat")?;
-    fmt_if_present(&mut to_modify, "exp")?;
-    fmt_if_present(&mut to_modify, "nbf")?;
+    for pointer in &readable.claims {
+        let mut res = Ok(());
+        for_each_pointer_match(&mut to_modify, pointer, &mut |val| {
+            if res.is_ok() {
+                res = fmt_numeric_date(val, readable);
+            }
+        });
+        res?;
+    }
     serde_json::to_string_pretty(&to_modify)
         .map_err(|_e| format!("Failed to pretty print json='{to_modify}'"))
 }
 
-fn fmt_if_present(json: &mut serde_json::Value, path: &'static str) -> Result<(), String> {
-    let Some(val) = json.get_mut(path) else {
-        return Ok(());
-    };
-    let Some(seconds_since_epoch) = val.as_i64() else {
+/// [RFC-7519](https://datatracker.ietf.org/doc/html/rfc7519#section-2)
+/// A NumericDate may be fractional, values that aren't numbers are left as is
+fn fmt_numeric_date(val: &mut serde_json::Value, readable: &ReadableTime) -> Result<(), String> {
+    let (seconds_since_epoch, nanos) = if let Some(seconds) = val.as_i64() {
+        (seconds, 0)
+    } else if let Some(fractional) = val.as_f64() {
+        let seconds = floor(fractional);
+        let nanos = ((fractional - seconds) * 1_000_000_000.0) as u32;
+        (seconds as i64, nanos.min(999_999_999))
+    } else {
         return Ok(());
     };
-    let now = SystemTime::now().duration_since_unix_time().as_secs() as i64;
-    let until = seconds_since_epoch - now;
-    let odt = time::OffsetDateTime::from_unix_timestamp(seconds_since_epoch).map_err(|_e| {
-        format!("Failed to convert field at {path} ({seconds_since_epoch}) to an offset datetime")
-    })?;
+    let until = seconds_since_epoch - readable.now;
+    let timestamp = fmt_rfc3339(
+        seconds_since_epoch,
+        nanos,
+        readable.tz.offset_at(seconds_since_epoch),
+    )
+    .ok_or_else(|| format!("Failed to convert NumericDate {val} to an offset datetime"))?;
+    let duration = fmt_duration(until.unsigned_abs());
     if until >= 0 {
-        *val = serde_json::Value::String(format!(
-            "{seconds_since_epoch} [{odt} (In {until} seconds)]"
-        ));
+        *val = serde_json::Value::String(format!("{val} [{timestamp} (in {duration})]"));
     } else {
-        let before = -until;
-        *val = serde_json::Value::String(format!(
-            "{seconds_since_epoch} [{odt} ({before} seconds ago)]"
-        ));
+        *val = serde_json::Value::String(format!("{val} [{timestamp} ({duration} ago)]"));
     }
     Ok(())
 }
 
-#[derive(Debug)]
+/// `f64::floor` requires std
+fn floor(value: f64) -> f64 {
+    let truncated = value as i64 as f64;
+    if truncated > value {
+        truncated - 1.0
+    } else {
+        truncated
+    }
+}
+
+/// [RFC-3339](https://datatracker.ietf.org/doc/html/rfc3339#section-5.6)
+/// `time`'s formatting requires std, so it's done by hand
+fn fmt_rfc3339(seconds_since_epoch: i64, nanos: u32, offset: i32) -> Option<String> {
+    let offset = time::UtcOffset::from_whole_seconds(offset).ok()?;
+    let odt = time::OffsetDateTime::from_unix_timestamp(seconds_since_epoch)
+        .ok()?
+        .checked_to_offset(offset)?;
+    let zone = if offset.is_utc() {
+        "Z".to_string()
+    } else {
+        let sign = if offset.is_negative() { '-' } else { '+' };
+        format!(
+            "{sign}{:02}:{:02}",
+            offset.whole_hours().unsigned_abs(),
+            offset.minutes_past_hour().unsigned_abs()
+        )
+    };
+    let fraction = if nanos == 0 {
+        String::new()
+    } else {
+        format!(".{nanos:09}").trim_end_matches('0').to_string()
+    };
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{fraction}{zone}",
+        odt.year(),
+        u8::from(odt.month()),
+        odt.day(),
+        odt.hour(),
+        odt.minute(),
+        odt.second()
+    ))
+}
+
+/// Like `1d 11h 4m 3s`, units that are zero are left out
+fn fmt_duration(seconds: u64) -> String {
+    if seconds == 0 {
+        return "0s".to_string();
+    }
+    let mut out = String::new();
+    for (count, unit) in [
+        (seconds / 86400, "d"),
+        (seconds % 86400 / 3600, "h"),
+        (seconds % 3600 / 60, "m"),
+        (seconds % 60, "s"),
+    ] {
+        if count != 0 {
+            if !out.is_empty() {
+                out.push(' ');
+            }
+            out.push_str(&format!("{count}{unit}"));
+        }
+    }
+    out
+}
+
+#[derive(Debug, Copy, Clone)]
 enum Output {
     Payload,
     Header,
+    /// Everything about the token as a single JSON object
+    Json,
 }
 
 impl Output {
@@ -130,7 +1221,31 @@ impl Output {
         {
             "header" => Ok(Self::Header),
             "payload" => Ok(Self::Payload),
+            "json" => Ok(Self::Json),
             unk => Err(format!("Unknown output selection: {unk}")),
         }
     }
 }
+
+#[derive(Debug, Copy, Clone)]
+enum RawFormat {
+    Hex,
+    Hexdump,
+    Base64,
+    Binary,
+}
+
+impl RawFormat {
+    fn parse(input: &'static UnixStr) -> Result<Self, String> {
+        match input
+            .as_str()
+            .map_err(|e| format!("Specified raw format was not valid utf8: {e}"))?
+        {
+            "hex" => Ok(Self::Hex),
+            "hexdump" => Ok(Self::Hexdump),
+            "base64" => Ok(Self::Base64),
+            "binary" => Ok(Self::Binary),
+            unk => Err(format!("Unknown raw format: {unk}")),
+        }
+    }
+}
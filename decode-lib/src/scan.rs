@@ -0,0 +1,94 @@
+use crate::jwt::try_decode_b64;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// A compact token found in some text
+pub(crate) struct FoundToken {
+    /// Of the token's first byte, from 0
+    pub offset: usize,
+    /// From 1
+    pub line: usize,
+    pub token: String,
+}
+
+/// [RFC-7515](https://datatracker.ietf.org/doc/html/rfc7515#section-7.1)
+/// Finds every compact JWS or JWE in text like logs, headers, cookies, or URLs.
+/// A token is three, or five, base64url segments separated by dots, where the first
+/// segment decodes to a JSON object with an `alg`. A JWE is told apart by its `enc`.
+/// Whether the rest of the token is valid is left to decoding it
+pub(crate) fn scan_tokens(input: &[u8]) -> Vec<FoundToken> {
+    let mut found = Vec::new();
+    let mut pos = 0;
+    // Counted as the input is walked, a newline is never part of a run
+    let mut line = 1;
+    while pos < input.len() {
+        if !is_token_byte(input[pos]) {
+            if input[pos] == b'\n' {
+                line += 1;
+            }
+            pos += 1;
+            continue;
+        }
+        let start = pos;
+        while pos < input.len() && is_token_byte(input[pos]) {
+            pos += 1;
+        }
+        scan_run(input, start, pos, line, &mut found);
+    }
+    found
+}
+
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.')
+}
+
+/// A run of base64url characters and dots may hold several tokens, or one with stray
+/// segments around it, like a sentence ending with a period
+fn scan_run(input: &[u8], start: usize, end: usize, line: usize, found: &mut Vec<FoundToken>) {
+    // Only base64url and dots, so it's ascii
+    let run = core::str::from_utf8(&input[start..end]).unwrap_or_default();
+    let mut segments = Vec::new();
+    let mut segment_start = 0;
+    for (ind, byte) in run.bytes().enumerate() {
+        if byte == b'.' {
+            segments.push((segment_start, ind));
+            segment_start = ind + 1;
+        }
+    }
+    segments.push((segment_start, run.len()));
+    let mut ind = 0;
+    while ind < segments.len() {
+        let (header_start, header_end) = segments[ind];
+        let Some(segment_count) = header_segment_count(&run[header_start..header_end]) else {
+            ind += 1;
+            continue;
+        };
+        let Some((_, token_end)) = segments.get(ind + segment_count - 1).copied() else {
+            ind += 1;
+            continue;
+        };
+        found.push(FoundToken {
+            offset: start + header_start,
+            line,
+            token: run[header_start..token_end].to_string(),
+        });
+        ind += segment_count;
+    }
+}
+
+/// [RFC-7516](https://datatracker.ietf.org/doc/html/rfc7516#section-9)
+/// The number of segments of the token the segment is the header of, if it's a header
+fn header_segment_count(segment: &str) -> Option<usize> {
+    if segment.is_empty() {
+        return None;
+    }
+    let header: serde_json::Value = serde_json::from_slice(&try_decode_b64(segment).ok()?).ok()?;
+    let header = header
+        .as_object()
+        .filter(|header| header.contains_key("alg"))?;
+    if header.contains_key("enc") {
+        Some(5)
+    } else {
+        Some(3)
+    }
+}
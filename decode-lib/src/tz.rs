@@ -0,0 +1,406 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use time::{Date, Month, OffsetDateTime};
+
+/// The zone that readable times are shown in
+pub(crate) enum TimeZone {
+    Utc,
+    /// Seconds east of UTC
+    Fixed(i32),
+    Tzif(Tzif),
+}
+
+impl TimeZone {
+    /// Accepts `UTC`, a fixed offset like `+02:00`, `local` for `/etc/localtime`, or an absolute
+    /// path to a TZif file
+    pub(crate) fn parse(tz: Option<&str>) -> Result<Self, String> {
+        match tz {
+            None | Some("UTC" | "utc" | "Z") => Ok(Self::Utc),
+            Some("local") => Self::load_tzif("/etc/localtime"),
+            Some(path) if path.starts_with('/') => Self::load_tzif(path),
+            Some(offset) => parse_fixed_offset(offset)
+                .map(Self::Fixed)
+                .ok_or_else(|| {
+                    format!("Failed to parse time zone '{offset}', expected `UTC`, an offset like `+02:00`, `local`, or a path to a TZif file")
+                }),
+        }
+    }
+
+    fn load_tzif(path: &str) -> Result<Self, String> {
+        let unix_path = tiny_std::UnixString::try_from_str(path)
+            .map_err(|e| format!("Invalid time zone path '{path}': {e}"))?;
+        let raw = tiny_std::fs::read(&unix_path)
+            .map_err(|e| format!("Failed to read time zone from path: {path}, e: {e}"))?;
+        Tzif::parse(&raw)
+            .map(Self::Tzif)
+            .map_err(|e| format!("Failed to parse time zone file {path}: {e}"))
+    }
+
+    /// Seconds east of UTC at the instant
+    pub(crate) fn offset_at(&self, unix: i64) -> i32 {
+        match self {
+            TimeZone::Utc => 0,
+            TimeZone::Fixed(offset) => *offset,
+            TimeZone::Tzif(tzif) => tzif.offset_at(unix),
+        }
+    }
+}
+
+/// `+hh:mm`, `-hh:mm`, `+hhmm`, or `+hh`
+fn parse_fixed_offset(raw: &str) -> Option<i32> {
+    let (sign, rest) = match raw.as_bytes().first()? {
+        b'+' => (1, &raw[1..]),
+        b'-' => (-1, &raw[1..]),
+        _ => return None,
+    };
+    let digits = rest.replace(':', "");
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (
+            digits[..2].parse::<i32>().ok()?,
+            digits[2..].parse::<i32>().ok()?,
+        ),
+        _ => return None,
+    };
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// [RFC-8536](https://datatracker.ietf.org/doc/html/rfc8536)
+pub(crate) struct Tzif {
+    /// Transition instants, and the offset from then on, ascending
+    transitions: Vec<(i64, i32)>,
+    /// The offset before the first transition
+    initial: i32,
+    /// Offsets after the last transition
+    footer: Option<PosixTz>,
+}
+
+impl Tzif {
+    fn parse(raw: &[u8]) -> Result<Self, String> {
+        let header = TzifHeader::parse(raw)?;
+        let (header, data, time_size) = if header.version >= b'2' {
+            // The version 1 data block is followed by a second header and data block with
+            // 64-bit times
+            let rest = header
+                .block_len(4)?
+                .checked_add(44)
+                .and_then(|end| raw.get(end..))
+                .ok_or_else(|| "Truncated version 1 data block".to_string())?;
+            (TzifHeader::parse(rest)?, &rest[44..], 8)
+        } else {
+            (header, &raw[44..], 4)
+        };
+        let block_len = header.block_len(time_size)?;
+        // Every offset into the data block below is within it, so can't overflow
+        if data.len() < block_len {
+            return Err("Truncated data block".to_string());
+        }
+        let times = &data[..header.timecnt * time_size];
+        let indices = &data[header.timecnt * time_size..header.timecnt * (time_size + 1)];
+        let types_start = header.timecnt * (time_size + 1);
+        let offsets = (0..header.typecnt)
+            .map(|ind| {
+                let at = types_start + ind * 6;
+                i32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+            })
+            .collect::<Vec<_>>();
+        let initial = *offsets
+            .first()
+            .ok_or_else(|| "No local time types".to_string())?;
+        let mut transitions = Vec::with_capacity(header.timecnt);
+        for (ind, type_ind) in indices.iter().enumerate() {
+            let bytes = &times[ind * time_size..(ind + 1) * time_size];
+            let at = if time_size == 8 {
+                i64::from_be_bytes(bytes.try_into().unwrap_or_default())
+            } else {
+                i64::from(i32::from_be_bytes(bytes.try_into().unwrap_or_default()))
+            };
+            let offset = *offsets
+                .get(usize::from(*type_ind))
+                .ok_or_else(|| format!("Transition to unknown local time type {type_ind}"))?;
+            transitions.push((at, offset));
+        }
+        let footer = if time_size == 8 {
+            let footer = &data[block_len..];
+            core::str::from_utf8(footer)
+                .ok()
+                .and_then(|footer| footer.strip_prefix('\n'))
+                .and_then(|footer| footer.split('\n').next())
+                .filter(|tz| !tz.is_empty())
+                .map(PosixTz::parse)
+                .transpose()?
+        } else {
+            None
+        };
+        Ok(Self {
+            transitions,
+            initial,
+            footer,
+        })
+    }
+
+    fn offset_at(&self, unix: i64) -> i32 {
+        match self.transitions.last() {
+            Some((last, _)) if unix >= *last => {
+                if let Some(footer) = &self.footer {
+                    return footer.offset_at(unix);
+                }
+            }
+            None => {
+                return self
+                    .footer
+                    .as_ref()
+                    .map_or(self.initial, |footer| footer.offset_at(unix))
+            }
+            _ => {}
+        }
+        match self.transitions.binary_search_by_key(&unix, |(at, _)| *at) {
+            Ok(ind) => self.transitions[ind].1,
+            Err(0) => self.initial,
+            Err(ind) => self.transitions[ind - 1].1,
+        }
+    }
+}
+
+struct TzifHeader {
+    version: u8,
+    isutcnt: usize,
+    isstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
+
+impl TzifHeader {
+    fn parse(raw: &[u8]) -> Result<Self, String> {
+        if raw.len() < 44 || &raw[..4] != b"TZif" {
+            return Err("Not a TZif file".to_string());
+        }
+        let count = |ind: usize| {
+            let at = 20 + ind * 4;
+            u32::from_be_bytes([raw[at], raw[at + 1], raw[at + 2], raw[at + 3]]) as usize
+        };
+        Ok(Self {
+            version: raw[4],
+            isutcnt: count(0),
+            isstdcnt: count(1),
+            leapcnt: count(2),
+            timecnt: count(3),
+            typecnt: count(4),
+            charcnt: count(5),
+        })
+    }
+
+    /// The counts are straight from the file, a crafted one could overflow
+    fn block_len(&self, time_size: usize) -> Result<usize, String> {
+        [
+            self.timecnt.checked_mul(time_size + 1),
+            self.typecnt.checked_mul(6),
+            Some(self.charcnt),
+            self.leapcnt.checked_mul(time_size + 4),
+            Some(self.isstdcnt),
+            Some(self.isutcnt),
+        ]
+        .into_iter()
+        .try_fold(0usize, |len, part| len.checked_add(part?))
+        .ok_or_else(|| "The data block's counts are too large".to_string())
+    }
+}
+
+/// [POSIX TZ](https://pubs.opengroup.org/onlinepubs/9699919799/basedefs/V1_chap08.html),
+/// like `CET-1CEST,M3.5.0,M10.5.0/3`, used for times after a TZif file's last transition
+struct PosixTz {
+    /// Seconds east of UTC
+    std_offset: i32,
+    dst: Option<Dst>,
+}
+
+struct Dst {
+    /// Seconds east of UTC
+    offset: i32,
+    start: (Rule, i32),
+    end: (Rule, i32),
+}
+
+/// The day a change to or from daylight saving time happens
+enum Rule {
+    /// `Jn`, 1 to 365, February 29th is never counted
+    Julian(u16),
+    /// `n`, 0 to 365, February 29th is counted
+    Ordinal(u16),
+    /// `Mm.w.d`, day `d` (0 is Sunday) of week `w` (5 is the last) of month `m`
+    MonthWeekDay(u8, u8, u8),
+}
+
+impl PosixTz {
+    fn parse(raw: &str) -> Result<Self, String> {
+        let err = || format!("Invalid POSIX TZ string '{raw}'");
+        let rest = skip_name(raw).ok_or_else(err)?;
+        let (std_offset, rest) = parse_hms(rest).ok_or_else(err)?;
+        // POSIX offsets are west of UTC
+        let std_offset = -std_offset;
+        if rest.is_empty() {
+            return Ok(Self {
+                std_offset,
+                dst: None,
+            });
+        }
+        let rest = skip_name(rest).ok_or_else(err)?;
+        let (offset, rest) = match parse_hms(rest) {
+            Some((offset, rest)) => (-offset, rest),
+            None => (std_offset + 3600, rest),
+        };
+        // Without rules, the US rules are the default
+        let rules = rest.strip_prefix(',').unwrap_or("M3.2.0,M11.1.0");
+        let (start, end) = rules.split_once(',').ok_or_else(err)?;
+        let start = parse_rule(start).ok_or_else(err)?;
+        let end = parse_rule(end).ok_or_else(err)?;
+        Ok(Self {
+            std_offset,
+            dst: Some(Dst { offset, start, end }),
+        })
+    }
+
+    fn offset_at(&self, unix: i64) -> i32 {
+        let Some(dst) = &self.dst else {
+            return self.std_offset;
+        };
+        let Ok(local) = OffsetDateTime::from_unix_timestamp(unix + i64::from(self.std_offset))
+        else {
+            return self.std_offset;
+        };
+        let year = local.year();
+        // The start is given in standard time, and the end in daylight saving time
+        let (Some(start), Some(end)) = (
+            transition_at(year, &dst.start, self.std_offset),
+            transition_at(year, &dst.end, dst.offset),
+        ) else {
+            return self.std_offset;
+        };
+        let in_dst = if start < end {
+            start <= unix && unix < end
+        } else {
+            // Southern hemisphere, daylight saving time spans the new year
+            !(end <= unix && unix < start)
+        };
+        if in_dst {
+            dst.offset
+        } else {
+            self.std_offset
+        }
+    }
+}
+
+/// Alphabetic, or quoted in `<>`, returns the rest
+fn skip_name(raw: &str) -> Option<&str> {
+    if let Some(quoted) = raw.strip_prefix('<') {
+        let (_name, rest) = quoted.split_once('>')?;
+        return Some(rest);
+    }
+    let len = raw.bytes().take_while(u8::is_ascii_alphabetic).count();
+    (len >= 3).then(|| &raw[len..])
+}
+
+/// `[+-]hh[:mm[:ss]]` in seconds, returns the rest
+fn parse_hms(raw: &str) -> Option<(i32, &str)> {
+    let (sign, unsigned) = match raw.as_bytes().first()? {
+        b'-' => (-1, &raw[1..]),
+        b'+' => (1, &raw[1..]),
+        _ => (1, raw),
+    };
+    let len = unsigned
+        .bytes()
+        .take_while(|b| b.is_ascii_digit() || *b == b':')
+        .count();
+    if len == 0 {
+        return None;
+    }
+    let mut seconds = 0;
+    let mut multiplier = 3600;
+    for part in unsigned[..len].split(':') {
+        if multiplier == 0 {
+            return None;
+        }
+        seconds += part.parse::<i32>().ok()? * multiplier;
+        multiplier /= 60;
+    }
+    Some((sign * seconds, &unsigned[len..]))
+}
+
+/// A rule with an optional `/time`, which defaults to 02:00:00
+fn parse_rule(raw: &str) -> Option<(Rule, i32)> {
+    let (rule, time) = match raw.split_once('/') {
+        Some((rule, time)) => {
+            let (time, rest) = parse_hms(time)?;
+            if !rest.is_empty() {
+                return None;
+            }
+            (rule, time)
+        }
+        None => (raw, 7200),
+    };
+    let rule = if let Some(julian) = rule.strip_prefix('J') {
+        Rule::Julian(julian.parse().ok().filter(|day| (1..=365).contains(day))?)
+    } else if let Some(mwd) = rule.strip_prefix('M') {
+        let mut parts = mwd.split('.').map(str::parse::<u8>);
+        let (Some(Ok(month)), Some(Ok(week)), Some(Ok(day)), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+        if !(1..=12).contains(&month) || !(1..=5).contains(&week) || day > 6 {
+            return None;
+        }
+        Rule::MonthWeekDay(month, week, day)
+    } else {
+        Rule::Ordinal(rule.parse().ok().filter(|day| *day <= 365)?)
+    };
+    Some((rule, time))
+}
+
+/// `time`'s own helper for this has been renamed between patch versions
+fn days_in_month(year: i32, month: Month) -> u8 {
+    match month {
+        Month::February if time::util::is_leap_year(year) => 29,
+        Month::February => 28,
+        Month::April | Month::June | Month::September | Month::November => 30,
+        _ => 31,
+    }
+}
+
+/// The instant of a transition in a year, the rule's time is local with the offset
+fn transition_at(year: i32, (rule, time): &(Rule, i32), offset: i32) -> Option<i64> {
+    let date = match rule {
+        Rule::Julian(day) => {
+            let date = Date::from_ordinal_date(year, *day).ok()?;
+            // February 29th is skipped
+            if time::util::is_leap_year(year) && *day >= 60 {
+                date.next_day()?
+            } else {
+                date
+            }
+        }
+        Rule::Ordinal(day) => Date::from_ordinal_date(year, *day + 1).ok()?,
+        Rule::MonthWeekDay(month, week, day) => {
+            let month = Month::try_from(*month).ok()?;
+            let first = Date::from_calendar_date(year, month, 1).ok()?;
+            let first_weekday = first.weekday().number_days_from_sunday();
+            let mut day_of_month = 1 + (7 + day - first_weekday) % 7 + (week - 1) * 7;
+            let days_in_month = days_in_month(year, month);
+            while day_of_month > days_in_month {
+                day_of_month -= 7;
+            }
+            Date::from_calendar_date(year, month, day_of_month).ok()?
+        }
+    };
+    let midnight = date.midnight().assume_utc().unix_timestamp();
+    Some(midnight + i64::from(*time) - i64::from(offset))
+}
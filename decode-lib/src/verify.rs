@@ -0,0 +1,329 @@
+use crate::jwk::JwkSet;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use pkcs8::{AssociatedOid, DecodePublicKey, Document};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::signature::Verifier;
+use rsa::RsaPublicKey;
+use sha2::digest::FixedOutputReset;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use tiny_std::UnixStr;
+
+/// Key material used to check a signature
+pub(crate) enum VerificationKey {
+    /// Shared secret for the `HS*` family
+    Hmac(Vec<u8>),
+    /// Public key for the `RS*` and `PS*` families
+    Rsa(RsaPublicKey),
+    /// Public key for `ES256`
+    P256(p256::ecdsa::VerifyingKey),
+    /// Public key for `ES384`
+    P384(p384::ecdsa::VerifyingKey),
+    /// Public key for `ES512`
+    P521(p521::ecdsa::VerifyingKey),
+    /// Public key for `EdDSA`, on curve Ed25519
+    Ed25519(ed25519_dalek::VerifyingKey),
+    /// Public key for `EdDSA`, on curve Ed448
+    Ed448(ed448_goldilocks_plus::VerifyingKey),
+}
+
+/// Where the key used to check a signature comes from
+pub(crate) enum KeySource {
+    Key(Box<VerificationKey>),
+    /// The key is picked from the set based on the token's header
+    Jwks(JwkSet),
+}
+
+impl KeySource {
+    /// Secrets are never taken from argv, since that would leak them into shell history and
+    /// process listings.
+    pub(crate) fn load(
+        secret_path: Option<&'static UnixStr>,
+        secret_env: Option<&'static UnixStr>,
+        key_path: Option<&'static UnixStr>,
+        jwks_path: Option<&'static UnixStr>,
+    ) -> Result<Self, String> {
+        match (secret_path, secret_env, key_path, jwks_path) {
+            (Some(path), None, None, None) => {
+                let secret = tiny_std::fs::read(path)
+                    .map_err(|e| format!("Failed to read secret from path: {path:?}, e: {e}"))?;
+                Ok(Self::Key(Box::new(VerificationKey::Hmac(secret))))
+            }
+            (None, Some(var), None, None) => {
+                let secret = tiny_std::env::var_unix(var).map_err(|_e| {
+                    format!("Failed to read secret from environment variable {var:?}")
+                })?;
+                // The variable comes with its NUL terminator, which isn't part of the secret
+                let secret = secret.as_slice();
+                Ok(Self::Key(Box::new(VerificationKey::Hmac(
+                    secret.strip_suffix(b"\0").unwrap_or(secret).to_vec(),
+                ))))
+            }
+            (None, None, Some(path), None) => {
+                let raw = tiny_std::fs::read(path)
+                    .map_err(|e| format!("Failed to read key from path: {path:?}, e: {e}"))?;
+                VerificationKey::parse_public_key(&raw).map(|key| Self::Key(Box::new(key)))
+            }
+            (None, None, None, Some(path)) => {
+                let raw = tiny_std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read JWKS from path: {path:?}, e: {e}"))?;
+                JwkSet::parse(&raw).map(Self::Jwks)
+            }
+            (None, None, None, None) => Err(
+                "Signature verification requires a key, supply one through `--secret-path`, `--secret-env`, `--key-path`, or `--jwks`"
+                    .to_string(),
+            ),
+            _ => Err(
+                "Supplied more than one key, use only one of `--secret-path`, `--secret-env`, `--key-path`, or `--jwks`"
+                    .to_string(),
+            ),
+        }
+    }
+
+    /// Returns the key to check the token with, and its `kid` if it was picked from a JWKS
+    pub(crate) fn select(
+        &self,
+        header: &serde_json::Value,
+        alg: &str,
+    ) -> Result<(&VerificationKey, Option<&str>), String> {
+        match self {
+            KeySource::Key(key) => Ok((key, None)),
+            KeySource::Jwks(jwks) => {
+                let kid = match header.get("kid") {
+                    None => None,
+                    Some(serde_json::Value::String(kid)) => Some(kid.as_str()),
+                    Some(other) => {
+                        return Err(format!(
+                            "The header's kid-field was not a string: '{other}'"
+                        ))
+                    }
+                };
+                jwks.select(kid, alg)
+            }
+        }
+    }
+}
+
+impl VerificationKey {
+    pub(crate) fn supports(&self, alg: &str) -> bool {
+        match self {
+            VerificationKey::Hmac(_) => matches!(alg, "HS256" | "HS384" | "HS512"),
+            VerificationKey::Rsa(_) => matches!(
+                alg,
+                "RS256" | "RS384" | "RS512" | "PS256" | "PS384" | "PS512"
+            ),
+            VerificationKey::P256(_) => alg == "ES256",
+            VerificationKey::P384(_) => alg == "ES384",
+            VerificationKey::P521(_) => alg == "ES512",
+            VerificationKey::Ed25519(_) | VerificationKey::Ed448(_) => alg == "EdDSA",
+        }
+    }
+
+    /// Accepts PEM or DER, either as a `SubjectPublicKeyInfo` or as a `PKCS#1` `RSAPublicKey`
+    fn parse_public_key(raw: &[u8]) -> Result<Self, String> {
+        if let Some(pem) = core::str::from_utf8(raw)
+            .ok()
+            .map(str::trim)
+            .filter(|s| s.starts_with("-----BEGIN"))
+        {
+            let (label, der) =
+                Document::from_pem(pem).map_err(|e| format!("Failed to parse key as PEM: {e}"))?;
+            if label == "RSA PUBLIC KEY" {
+                return RsaPublicKey::from_pkcs1_der(der.as_bytes())
+                    .map(Self::Rsa)
+                    .map_err(|e| format!("Failed to parse PEM key as an RSA public key: {e}"));
+            }
+            return Self::parse_der_public_key(der.as_bytes());
+        }
+        Self::parse_der_public_key(raw)
+    }
+
+    fn parse_der_public_key(der: &[u8]) -> Result<Self, String> {
+        if let Ok(key) = RsaPublicKey::from_public_key_der(der) {
+            return Ok(Self::Rsa(key));
+        }
+        if let Ok(key) = p256::PublicKey::from_public_key_der(der) {
+            return Ok(Self::P256(key.into()));
+        }
+        if let Ok(key) = p384::PublicKey::from_public_key_der(der) {
+            return Ok(Self::P384(key.into()));
+        }
+        if let Ok(key) = p521::PublicKey::from_public_key_der(der) {
+            return p521::ecdsa::VerifyingKey::from_affine(*key.as_affine())
+                .map(Self::P521)
+                .map_err(|e| {
+                    format!("Failed to convert P-521 public key to a verifying key: {e}")
+                });
+        }
+        if let Ok(key) = ed25519_dalek::VerifyingKey::from_public_key_der(der) {
+            return Ok(Self::Ed25519(key));
+        }
+        if let Ok(key) = ed448_goldilocks_plus::VerifyingKey::from_public_key_der(der) {
+            return Ok(Self::Ed448(key));
+        }
+        RsaPublicKey::from_pkcs1_der(der)
+            .map(Self::Rsa)
+            .map_err(|_e| {
+                "Failed to parse key as an RSA, P-256, P-384, P-521, Ed25519, or Ed448 public key"
+                    .to_string()
+            })
+    }
+}
+
+/// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-3.1)
+/// The key has to fit the alg, an RSA key can't be used to check an `HS256` signature etc.
+/// For `EdDSA` the curve is decided by the key.
+/// `Ok(false)` means the signature was checked and did not match, `Err` means that it could not
+/// be checked at all.
+pub(crate) fn verify_signature(
+    alg: &str,
+    key: &VerificationKey,
+    signing_input: &[u8],
+    signature: &[u8],
+) -> Result<bool, String> {
+    match (alg, key) {
+        ("HS256", VerificationKey::Hmac(secret)) => {
+            verify_hmac::<Hmac<Sha256>>(secret, signing_input, signature)
+        }
+        ("HS384", VerificationKey::Hmac(secret)) => {
+            verify_hmac::<Hmac<Sha384>>(secret, signing_input, signature)
+        }
+        ("HS512", VerificationKey::Hmac(secret)) => {
+            verify_hmac::<Hmac<Sha512>>(secret, signing_input, signature)
+        }
+        ("RS256", VerificationKey::Rsa(key)) => {
+            verify_rsa_pkcs1v15::<Sha256>(key, signing_input, signature)
+        }
+        ("RS384", VerificationKey::Rsa(key)) => {
+            verify_rsa_pkcs1v15::<Sha384>(key, signing_input, signature)
+        }
+        ("RS512", VerificationKey::Rsa(key)) => {
+            verify_rsa_pkcs1v15::<Sha512>(key, signing_input, signature)
+        }
+        ("PS256", VerificationKey::Rsa(key)) => {
+            verify_rsa_pss::<Sha256>(key, signing_input, signature)
+        }
+        ("PS384", VerificationKey::Rsa(key)) => {
+            verify_rsa_pss::<Sha384>(key, signing_input, signature)
+        }
+        ("PS512", VerificationKey::Rsa(key)) => {
+            verify_rsa_pss::<Sha512>(key, signing_input, signature)
+        }
+        ("ES256", VerificationKey::P256(key)) => Ok(p256::ecdsa::Signature::from_slice(signature)
+            .is_ok_and(|signature| key.verify(signing_input, &signature).is_ok())),
+        ("ES384", VerificationKey::P384(key)) => Ok(p384::ecdsa::Signature::from_slice(signature)
+            .is_ok_and(|signature| key.verify(signing_input, &signature).is_ok())),
+        ("ES512", VerificationKey::P521(key)) => Ok(p521::ecdsa::Signature::from_slice(signature)
+            .is_ok_and(|signature| key.verify(signing_input, &signature).is_ok())),
+        ("EdDSA", VerificationKey::Ed25519(key)) => {
+            Ok(ed25519_dalek::Signature::from_slice(signature)
+                .is_ok_and(|signature| key.verify(signing_input, &signature).is_ok()))
+        }
+        ("EdDSA", VerificationKey::Ed448(key)) => {
+            Ok(ed448_goldilocks_plus::Signature::try_from(signature)
+                .is_ok_and(|signature| key.verify_raw(&signature, signing_input).is_ok()))
+        }
+        (unk, _) => Err(format!(
+            "Unsupported alg for signature verification with the supplied key: '{unk}'"
+        )),
+    }
+}
+
+/// The length in bytes that a signature made with an alg has
+pub(crate) enum SignatureLength {
+    /// `EdDSA` signatures differ in length between curves
+    OneOf(&'static [usize]),
+    /// RSA signatures are as long as the key's modulus, which has to be at least 2048 bits
+    AtLeast(usize),
+}
+
+impl SignatureLength {
+    /// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-3.1)
+    /// [RFC-8037](https://datatracker.ietf.org/doc/html/rfc8037#section-3.1)
+    pub(crate) fn of_alg(alg: &str) -> Option<Self> {
+        match alg {
+            "HS256" => Some(Self::OneOf(&[32])),
+            "HS384" => Some(Self::OneOf(&[48])),
+            "HS512" | "ES256" => Some(Self::OneOf(&[64])),
+            "ES384" => Some(Self::OneOf(&[96])),
+            "ES512" => Some(Self::OneOf(&[132])),
+            "EdDSA" => Some(Self::OneOf(&[64, 114])),
+            "RS256" | "RS384" | "RS512" | "PS256" | "PS384" | "PS512" => Some(Self::AtLeast(256)),
+            "none" => Some(Self::OneOf(&[0])),
+            // [RFC-9053](https://datatracker.ietf.org/doc/html/rfc9053#section-3.1)
+            "HMAC 256/64" => Some(Self::OneOf(&[8])),
+            "HMAC 256/256" => Some(Self::OneOf(&[32])),
+            "HMAC 384/384" => Some(Self::OneOf(&[48])),
+            "HMAC 512/512" => Some(Self::OneOf(&[64])),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn matches(&self, len: usize) -> bool {
+        match self {
+            SignatureLength::OneOf(lengths) => lengths.contains(&len),
+            SignatureLength::AtLeast(min) => len >= *min,
+        }
+    }
+}
+
+impl core::fmt::Display for SignatureLength {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SignatureLength::OneOf([len]) => write!(f, "{len}"),
+            SignatureLength::OneOf(lengths) => {
+                for (ind, len) in lengths.iter().enumerate() {
+                    if ind != 0 {
+                        f.write_str(" or ")?;
+                    }
+                    write!(f, "{len}")?;
+                }
+                Ok(())
+            }
+            SignatureLength::AtLeast(min) => write!(f, "at least {min}"),
+        }
+    }
+}
+
+fn verify_hmac<M: Mac + KeyInit>(
+    secret: &[u8],
+    signing_input: &[u8],
+    signature: &[u8],
+) -> Result<bool, String> {
+    let mut mac = <M as KeyInit>::new_from_slice(secret)
+        .map_err(|_e| "Failed to initialize hmac from the supplied secret".to_string())?;
+    mac.update(signing_input);
+    // Constant time comparison
+    Ok(mac.verify_slice(signature).is_ok())
+}
+
+/// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-3.3)
+fn verify_rsa_pkcs1v15<D: Digest + AssociatedOid>(
+    key: &RsaPublicKey,
+    signing_input: &[u8],
+    signature: &[u8],
+) -> Result<bool, String> {
+    let verifier = rsa::pkcs1v15::VerifyingKey::<D>::new(key.clone());
+    let Ok(signature) = rsa::pkcs1v15::Signature::try_from(signature) else {
+        return Ok(false);
+    };
+    Ok(verifier.verify(signing_input, &signature).is_ok())
+}
+
+/// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-3.5)
+/// Salt length is the same as the digest's output length
+fn verify_rsa_pss<D: Digest + FixedOutputReset>(
+    key: &RsaPublicKey,
+    signing_input: &[u8],
+    signature: &[u8],
+) -> Result<bool, String> {
+    let verifier = rsa::pss::VerifyingKey::<D>::new(key.clone());
+    let Ok(signature) = rsa::pss::Signature::try_from(signature) else {
+        return Ok(false);
+    };
+    Ok(verifier.verify(signing_input, &signature).is_ok())
+}
@@ -0,0 +1,45 @@
+#![no_main]
+
+use decode_lib::Args;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Passed as hex, since stdin has to be utf8
+    let hex = data
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    let args = Args {
+        stdin: Some(hex),
+        path: None,
+        env: None,
+        prompt: false,
+        max_input_size: None,
+        verbose: true,
+        readable_time: true,
+        output: None,
+        raw_format: None,
+        verify: false,
+        secret_path: None,
+        secret_env: None,
+        key_path: None,
+        jwks: None,
+        decrypt_key: None,
+        cwt: true,
+        batch: false,
+        scan: false,
+        detached_payload: None,
+        validate: false,
+        leeway: None,
+        expect_iss: None,
+        expect_aud: None,
+        expect_sub: None,
+        expect_azp: None,
+        claim: Vec::new(),
+        at: None,
+        tz: None,
+        time_claim: Vec::new(),
+    };
+    // Arbitrary CBOR can be anything, it just can't crash
+    let _ = decode_lib::run::run(args);
+});
@@ -0,0 +1,86 @@
+#![no_main]
+
+use base64::Engine;
+use decode_lib::run::MAX_NESTING_DEPTH;
+use decode_lib::Args;
+use libfuzzer_sys::arbitrary::{Arbitrary, Unstructured};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug)]
+pub struct NestedJwt {
+    inner: String,
+    depth: usize,
+}
+
+impl<'a> Arbitrary<'a> for NestedJwt {
+    fn arbitrary(u: &mut Unstructured<'a>) -> libfuzzer_sys::arbitrary::Result<Self> {
+        let header = b"{
+\"alg\": \"HS256\",
+\"typ\": \"JWT\"
+}";
+        let nested_header = b"{
+\"alg\": \"HS256\",
+\"cty\": \"JWT\"
+}";
+        let depth = u.int_in_range(0..=MAX_NESTING_DEPTH + 2)?;
+        // Prefixed so that the innermost body can't itself be a token, which would add a layer
+        let mut body = b"{".to_vec();
+        body.extend_from_slice(u.arbitrary()?);
+        let mut jwt = encode(header, &body, u.arbitrary()?);
+        for _ in 0..depth {
+            jwt = encode(nested_header, jwt.as_bytes(), u.arbitrary()?);
+        }
+        Ok(NestedJwt { inner: jwt, depth })
+    }
+}
+
+fn encode(header: &[u8], body: &[u8], sig: &[u8]) -> String {
+    let header = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(header);
+    let body = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(body);
+    let sig = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(sig);
+    format!("{header}.{body}.{sig}")
+}
+
+fuzz_target!(|data: NestedJwt| {
+    let args = Args {
+        stdin: Some(data.inner.clone()),
+        path: None,
+        env: None,
+        prompt: false,
+        max_input_size: None,
+        verbose: true,
+        readable_time: false,
+        output: None,
+        raw_format: None,
+        verify: false,
+        secret_path: None,
+        secret_env: None,
+        key_path: None,
+        jwks: None,
+        decrypt_key: None,
+        cwt: false,
+        batch: false,
+        scan: false,
+        detached_payload: None,
+        validate: false,
+        leeway: None,
+        expect_iss: None,
+        expect_aud: None,
+        expect_sub: None,
+        expect_azp: None,
+        claim: Vec::new(),
+        at: None,
+        tz: None,
+        time_claim: Vec::new(),
+    };
+    let res = decode_lib::run::run(args);
+    if data.depth <= MAX_NESTING_DEPTH {
+        assert!(res.is_ok(), "Got a error code from input={}", data.inner);
+    } else {
+        assert!(
+            res.is_err(),
+            "Accepted a token nested too deep={}",
+            data.inner
+        );
+    }
+});
@@ -30,9 +30,33 @@ fuzz_target!(|data: ValidJwt| {
     let args = Args {
         stdin: Some(data.inner.clone()),
         path: None,
+        env: None,
+        prompt: false,
+        max_input_size: None,
         verbose: false,
         readable_time: false,
         output: None,
+        raw_format: None,
+        verify: false,
+        secret_path: None,
+        secret_env: None,
+        key_path: None,
+        jwks: None,
+        decrypt_key: None,
+        cwt: false,
+        batch: false,
+        scan: false,
+        detached_payload: None,
+        validate: false,
+        leeway: None,
+        expect_iss: None,
+        expect_aud: None,
+        expect_sub: None,
+        expect_azp: None,
+        claim: Vec::new(),
+        at: None,
+        tz: None,
+        time_claim: Vec::new(),
     };
     // Odds of getting a valid JWT is really low
     assert!(